@@ -0,0 +1,30 @@
+; data directives, compared against nasm -f bin output.
+; dx.bin is NOT nasm output yet: it was written by hand from the rules
+; in the nasm manual and only guards against regressions until someone
+; with nasm runs `make regress-nasm` and commits the new dx.bin
+section .data
+; strings are zero filled up to a multiple of the item size
+db 'a', 'abc', "abcde", `a\tb`, 0
+dw 'a', 'ab', 'abc', 'abcde', `\x41\x42\x43`
+dd 'a', 'abcd', 'abcde', 'abcdefgh', "abcdefghi"
+dq 'a', 'abcdefgh', 'abcdefghi'
+dt 'abc'
+do 'abcdefghijklmnopq'
+dy 'abc'
+dz 'abc'
+; a character constant in an expression is a number
+dw 'a'+1, 'ab'
+dd 'abc'|0x80000000
+; integers
+db -1, 255, 0x7F
+dw -2, 0x1234
+dd -3, 0x12345678
+dq -4, 0x123456789ABCDEF0
+; floats
+dd 1.5, -2.0, __float32__(0.25)
+dq 1.5, 0.1, -0.5
+dt 1.0, 0.1, -3.0
+; utf strings
+dw __utf16__('hé')
+dd __utf16__('abc'), __utf32__(`x`)
+dq __utf16be__("A")
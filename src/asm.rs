@@ -1,10 +1,9 @@
-use nom::{AsBytes, IResult};
+use nom::IResult;
 use nom::character::complete::{
     space1,
     multispace0,
     alphanumeric0,
     one_of,
-    digit1,
 };
 use nom::multi::many0_count;
 use nom::bytes::complete::{
@@ -12,717 +11,1255 @@ use nom::bytes::complete::{
     take_until,
     take,
 };
-use nom::branch::alt;
 use nom::character::{
     is_newline,
     is_space,
     is_alphabetic,
     is_alphanumeric,
 };
-use std::fs::File;
-use std::io::prelude::*;
-use std::io::{
-    self,
-    BufWriter,
-    Write,
-    BufReader,
-    Read,
-};
-use std::cell::RefCell;
-use std::{iter, mem};
+use std::cell::{Cell, Ref, RefCell, RefMut};
+use crate::preproc::LineMap;
+use crate::diagnostic::{Code, Diagnostic, Span};
+use std::iter;
 mod reg;// load const registers
-use reg as r;
 use reg::Value;
 mod headers;
-use headers::*;
+mod addr;
+use addr::Mem;
+mod reloc;
+use reloc::{Relocation, RelocKind};
+mod encode;
+mod object;
+use object::{Bind, Module, Symbol};
+pub use object::{format, ObjectFormat};
+mod coff;
+mod elf;
+mod bin;
+mod insn;
+mod expr;
+use expr::Expr;
+mod constant;
+mod data;
+use data::get_quoted;
+// give up when labels keep moving
+const MAX_PASS: usize = 1000;
+// tttn of jcc, cmovcc and setcc
+const CONDITIONS: [(&str, u8); 30] = [
+    ("o", 0x0), ("no", 0x1),
+    ("b", 0x2), ("c", 0x2), ("nae", 0x2),
+    ("nb", 0x3), ("nc", 0x3), ("ae", 0x3),
+    ("e", 0x4), ("z", 0x4),
+    ("ne", 0x5), ("nz", 0x5),
+    ("be", 0x6), ("na", 0x6),
+    ("nbe", 0x7), ("a", 0x7),
+    ("s", 0x8), ("ns", 0x9),
+    ("p", 0xA), ("pe", 0xA),
+    ("np", 0xB), ("po", 0xB),
+    ("l", 0xC), ("nge", 0xC),
+    ("nl", 0xD), ("ge", 0xD),
+    ("le", 0xE), ("ng", 0xE),
+    ("nle", 0xF), ("g", 0xF),
+];
+// words of _instruction besides the database, dx, resx and jcc
+const DIRECTIVES: [&str; 11] = [
+    "section", "segment", "default", "org", "global", "extern", "common", "times", "align", "alignb", "incbin",
+];
+const BRANCHES: [&str; 9] = ["jmp", "call", "loop", "loope", "loopz", "loopne", "loopnz", "jrcxz", "jecxz"];
+const PREFIXES: [&str; 6] = ["rep", "repe", "repz", "repne", "repnz", "lock"];
+// mnemonics missing from the database, an error rather than a label
+const UNSUPPORTED: [&str; 28] = [
+    "in", "out", "ins", "outs", "insb", "outsb", "bt", "bts", "btr", "btc", "bsf", "bsr", "bswap", "xadd",
+    "cmpxchg", "cmpxchg8b", "cmpxchg16b", "shld", "shrd", "popcnt", "lzcnt", "tzcnt", "xlatb", "iretq",
+    "sysret", "lahf", "sahf", "rdtscp",
+];
+fn condition(cc: &str) -> Option<u8>{
+    CONDITIONS.iter().find(|(name, _)| *name == cc).map(|(_, tttn)| *tttn)
+}
 #[derive(Default)]
 pub struct Label<'a>{
     name: & 'a str,
     pos: usize,
-    section_number: usize
+    section_number: usize,
+    // last pass this label was defined in
+    pass: usize,
 }
 impl<'a> Label<'a>{
-    pub fn new(_name: & 'a str, _pos: usize, _section_number: usize) -> Self{
-        Self{name: _name, pos: _pos, section_number: _section_number}
+    pub fn new(_name: & 'a str, _pos: usize, _section_number: usize, _pass: usize) -> Self{
+        Self{name: _name, pos: _pos, section_number: _section_number, pass: _pass}
     }
 }
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum SectionKind{
+    #[default]
+    Code,
+    Data,
+    // nobits
+    Bss,
+    // read only data
+    Rdata,
+    // linker directives, not loaded
+    Info,
+}
+// kind and alignment of well-known sections,
+// others depend on the output format
+const SECTIONS: [(&str, SectionKind, u64); 8] = [
+    (".text", SectionKind::Code, 16),
+    (".data", SectionKind::Data, 4),
+    (".bss", SectionKind::Bss, 4),
+    (".rdata", SectionKind::Rdata, 8),
+    (".rodata", SectionKind::Rdata, 8),
+    (".pdata", SectionKind::Rdata, 4),
+    (".xdata", SectionKind::Rdata, 8),
+    (".drectve", SectionKind::Info, 1),
+];
 #[derive(Default)]
 pub struct Section<'a>{
     name : & 'a str,
     data : Vec<u8>,
-    number_of_relocations: usize,
+    relocations: Vec<Relocation<'a>>,
+    kind: SectionKind,
+    // alignment without align=, by the name
+    name_align: u64,
+    // size of a nobits section, which has no data
+    reserved: usize,
+    // exec/noexec and write/nowrite override the kind
+    exec: Option<bool>,
+    write: Option<bool>,
+    // align=, start= and follows= (bin)
+    align: Option<u64>,
+    start: Option<u64>,
+    follows: Option<& 'a str>,
 }
 impl<'a> Section<'a>{
-    pub fn new(_name :& 'a str) ->Self{
-        let mut ret = Self::default();
-        ret.name = _name;
-        ret
+    // other is the kind and alignment of unknown names
+    pub fn new(name :& 'a str, other: (SectionKind, u64)) ->Self{
+        let (kind, name_align) = SECTIONS.iter().find(|(n, _, _)| *n == name).map_or(other, |(_, kind, align)| (*kind, *align));
+        Self{name, kind, name_align, ..Default::default()}
     }
-    pub fn to_section_header(&self) -> SECTION_HEADER{
-        let mut sh = SECTION_HEADER::default();
-        sh.Name[..self.name.len()].copy_from_slice(self.name.as_bytes());
-        sh.SizeOfRawData = self.data.len() as u32;
-        sh.NumberOfRelocations = self.number_of_relocations as u16;
-        match self.name {
-            ".data" =>{
-                sh.Characteristics = 0xC0300040;
-            },
-            ".text" =>{
-                sh.Characteristics = 0x60500020;
-            },
-            ".bss" =>{
-                sh.Characteristics = 0xC0300080;
-            },
-            _ => {panic!("Can't use this section name: {}.", self.name);}
-        }
-        sh
+    // align= or the default of the name
+    pub fn alignment(&self) -> u64{
+        self.align.unwrap_or(self.name_align)
+    }
+    pub fn is_exec(&self) -> bool{
+        self.exec.unwrap_or(self.kind == SectionKind::Code)
+    }
+    pub fn is_write(&self) -> bool{
+        self.write.unwrap_or(matches!(self.kind, SectionKind::Data | SectionKind::Bss))
+    }
+    pub fn is_nobits(&self) -> bool{
+        self.kind == SectionKind::Bss
+    }
+    pub fn size(&self) -> usize{
+        if self.is_nobits() {self.reserved} else {self.data.len()}
     }
 }
 #[derive(Default)]
 pub struct Asm<'a>{
     m_file: & 'a str,
     m_contents: & 'a str,
+    // original file and line of each line of m_contents
+    m_map: Option<& 'a LineMap>,
+    // -I directories, for incbin
+    include: & 'a [String],
+    // kind and alignment of sections not in SECTIONS
+    other_section: (SectionKind, u64),
+    // files of incbin, read once for every pass
+    files: RefCell<Vec<(String, Vec<u8>)>>,
     sections: RefCell<Vec<Section<'a>>>,
     labels: RefCell<Vec<Label<'a>>>,
+    // equ symbols with the last pass they were defined in
+    equs: RefCell<Vec<(& 'a str, Expr<'a>, usize)>>,
+    default_rel: Cell<bool>,
+    // section number of the current section, 1 based
+    current: Cell<usize>,
+    // line being assembled
+    line: Cell<& 'a str>,
+    // position of the line in the current section, $
+    here: Cell<usize>,
+    // base address of the flat binary
+    org: Cell<u64>,
+    globals: RefCell<Vec<& 'a str>>,
+    externs: RefCell<Vec<& 'a str>>,
+    // name, size, align
+    commons: RefCell<Vec<(& 'a str, u64, u64)>>,
+    pass: Cell<usize>,
+    // a label moved in this pass
+    changed: Cell<bool>,
+    // branches which don't fit rel8, by order of appearance
+    long_branches: RefCell<Vec<bool>>,
+    branch_idx: Cell<usize>,
+    // errors which may disappear in a later pass
+    deferred: RefCell<Vec<(& 'a str, Code, & 'static str)>>,
+    // errors of lines which were given up
+    errors: RefCell<Vec<Diagnostic>>,
+    // warnings of this pass
+    warnings: RefCell<Vec<Diagnostic>>,
+    // the last ret or jmp, until a label
+    unreachable: Cell<Option<& 'a str>>,
 }
 
 impl<'a> Asm<'a>{
-    pub fn new(file: & 'a str, contents: & 'a str) -> Self{
+    pub fn new(file: & 'a str, contents: & 'a str, map: & 'a LineMap, include: & 'a [String], format: &dyn ObjectFormat) -> Self{
         let mut ret = Self::default();
         ret.m_file = file;
         ret.m_contents = contents;
+        ret.m_map = Some(map);
+        ret.include = include;
+        ret.other_section = format.other_section();
         ret
     }
-    pub fn debug(&self){
-        let sections = self.sections.borrow();
-        let mut sections = sections.iter();
-        while let &Some(section) = &sections.next(){
-            println!("#####{}#####", section.name);
-            for op in &section.data{
-                print!("{:0>2X} ", op);
+    fn asm_error(&self) -> AsmError<'a>{
+        AsmError::new(self.m_contents, self.m_map)
+    }
+    // all errors of the first failing pass, or of the last pass
+    pub fn start(&self) -> Result<(), Vec<Diagnostic>>{
+        // repeat until every label keeps its position.
+        // forward references use the position of the previous pass
+        loop{
+            self.pass.set(self.pass.get() + 1);
+            self.changed.set(false);
+            self.branch_idx.set(0);
+            self.default_rel.set(false);
+            self.sections.borrow_mut().clear();
+            self.current.set(0);
+            self.deferred.borrow_mut().clear();
+            self.globals.borrow_mut().clear();
+            self.externs.borrow_mut().clear();
+            self.commons.borrow_mut().clear();
+            self.warnings.borrow_mut().clear();
+            self.unreachable.set(None);
+            self.assemble();
+            // the same lines would fail again
+            if !self.errors.borrow().is_empty(){
+                return Err(self.errors.take());
+            }
+            if !self.changed.get(){
+                break;
+            }
+            if self.pass.get() >= MAX_PASS{
+                let note = format!("Gave up after {} passes.", MAX_PASS);
+                return Err(vec![Diagnostic::error(Code::NoConvergence, "Labels don't converge.").note(&note)]);
+            }
+        }
+        let ae = self.asm_error();
+        let mut errors: Vec<Diagnostic> = self.deferred.borrow().iter().map(|(word, code, message)| ae.diagnostic(word, *code, message)).collect();
+        for name in self.globals.borrow().iter(){
+            if self.find_label(name).is_none(){
+                errors.push(ae.diagnostic(name, Code::GlobalUndefined, "Global symbol is never defined."));
             }
-            println!();
         }
+        if errors.is_empty() {Ok(())} else {Err(errors)}
     }
-    pub fn start(&self){
+    fn defer_error(&self, word: & 'a str, code: Code, message: & 'static str){
+        self.deferred.borrow_mut().push((word, code, message));
+    }
+    // warnings of the last pass
+    pub fn warnings(&self) -> Vec<Diagnostic>{
+        self.warnings.take()
+    }
+    fn warn(&self, class: & 'static str, word: &str, message: &str){
+        let ae = self.asm_error();
+        let warning = Diagnostic::warning(class, message).label(ae.span_of(word), "");
+        self.warnings.borrow_mut().push(warning);
+    }
+    fn assemble(&self){
         let mut input;
         let mut lines = self.m_contents.lines();
         while let Some(s) = lines.next(){
             // 一行ずつ読み込んでいる
             input = s;
-            println!("{}", s);
-            loop {
-                if input == ""{
-                    break;
-                }
-                if let Ok((s, _)) = ignore_space(input){
-                    input = s;
-                    continue;
-                }
-                if is_ignore_comment(input){
-                    break;
-                }
-                input = self.label_or_instruction(input);
-                
+            self.line.set(s);
+            // an error gives up the rest of the line
+            if let Err(diagnostic) = self.assemble_line(input){
+                self.errors.borrow_mut().push(diagnostic);
             }
         }
     }
-    pub fn write(&self, filename: & 'a str){
-        let file = File::create(filename).unwrap();
-        let mut writer = BufWriter::new(file);
-
-        let sections = self.sections.borrow_mut();
-
-        let data_sumsize = |x: &Vec<Section>| -> usize {
-            let mut size = 0usize;
-            for s in x{
-                size += s.data.len();
+    fn assemble_line(&self, mut input: & 'a str) -> Result<(), Diagnostic>{
+        loop{
+            if input.is_empty(){
+                break;
+            }
+            if let Ok((s, _)) = ignore_space(input){
+                input = s;
+                continue;
+            }
+            if is_ignore_comment(input){
+                break;
+            }
+            input = self.label_or_instruction(input)?;
+        }
+        Ok(())
+    }
+    // serialise the assembled module
+    pub fn write(&self, format: &dyn ObjectFormat) -> Result<Vec<u8>, Vec<Diagnostic>>{
+        let ae = self.asm_error();
+        let mut errors = Vec::new();
+        let sections = self.sections.borrow();
+        let labels = self.labels.borrow();
+        let globals = self.globals.borrow();
+        // locals come first
+        let mut symbols: Vec<Symbol> = labels.iter().map(|label| {
+            let bind = if globals.contains(&label.name) {Bind::Global} else {Bind::Local};
+            Symbol{name: label.name, section: label.section_number, value: label.pos as u64, bind}
+        }).collect();
+        symbols.sort_by_key(|sym| sym.bind != Bind::Local);
+        for name in self.externs.borrow().iter(){
+            if !symbols.iter().any(|sym| sym.name == *name){
+                symbols.push(Symbol{name, section: 0, value: 0, bind: Bind::Extern});
             }
-            size
+        }
+        for &(name, size, align) in self.commons.borrow().iter(){
+            if symbols.iter().any(|sym| sym.name == name){
+                errors.push(ae.diagnostic(name, Code::CommonDefined, "Common symbol is already defined."));
+            }
+            symbols.push(Symbol{name, section: 0, value: size, bind: Bind::Common(align)});
+        }
+        let module = Module{
+            file: self.m_file,
+            contents: self.m_contents,
+            map: self.m_map,
+            sections: &sections,
+            symbols,
+            org: self.org.get(),
         };
-
-        let mut p_section: usize = mem::size_of::<FILE_HEADER>();
-        let mut p_data: usize = p_section + sections.len() * mem::size_of::<SECTION_HEADER>();
-
-        let mut file_headers = FILE_HEADER::new();
-        let mut section_headers = Vec::<SECTION_HEADER>::new();
-        let mut symbol_tables = Vec::<u8>::new();
-        // FILE_HEADER
-        file_headers.Machine = 0x8664;
-        file_headers.NumberOfSections = sections.len() as u16; // set later
-        file_headers.TimeDataStamp = chrono::Local::now().timestamp() as u32;
-        file_headers.PointerToSymbolTable = 0; // set later
-        file_headers.NumberOfSymbols = 0;// set later
-        file_headers.SizeOfOptionalHeader = 0;
-        file_headers.Characteristics = 0;
-        // SECTION
-        for i in 0..sections.len(){
-            let sec = &sections[i];
-            let mut section_header = sec.to_section_header();
-            
-            // set section
-            section_header.PointerToRawData = p_data as u32;
-            p_data += sec.data.len();
-            section_header.PointerToRelocations = p_data as u32;
-            p_data += sec.number_of_relocations * mem::size_of::<RELOCATION>();
-            
-            section_headers.push(section_header);
-        }
-        //relocations
-        // symbol
-        /*
-        * file symbol
-         */
-        let mut _sbl = SYMBOL_TABLE::new_dot_file();
-        symbol_tables.append(& mut as_u8_slice(&_sbl).to_vec());
-        let mut _sbl = [0u8; 0x12];
-        _sbl[..self.m_file.len()].copy_from_slice(self.m_file.as_bytes());
-        symbol_tables.append(& mut as_u8_slice(&_sbl).to_vec());
-        
-        for i in 0..sections.len(){
-            let sec = &sections[i];
-            //set symbols
-            let mut symbol = SYMBOL_TABLE::default();
-            symbol.Name[..sec.name.len()].copy_from_slice(sec.name.as_bytes());
-            symbol.SectionNumber = 1u16 + i as u16;
-            symbol.StorageClass = 3;
-            symbol.NumberOfAuxSymbols = 1;
-            let mut symbol_define_section = SYMBOL_U8::default();
-            symbol_define_section.set(&sec.data.len(), 0);
-            symbol_define_section.set(&sec.number_of_relocations, 4);
-            
-            symbol_tables.append(&mut as_u8_slice(&symbol).to_vec());
-            symbol_tables.append(&mut symbol_define_section.data.to_vec());
-        }
-        // * label
-        for label in &*self.labels.borrow(){
-            let mut symbol = SYMBOL_TABLE::default();
-            symbol.Name[..label.name.len()].copy_from_slice(label.name.as_bytes());
-            symbol.Value = label.pos as u32;
-            symbol.SectionNumber = label.section_number as u16;
-            symbol.StorageClass = 2;
-            symbol_tables.append(&mut as_u8_slice(&symbol).to_vec());
-        }
-        symbol_tables.append(&mut as_u8_slice(&4u32).to_vec());
-        // * set later
-        file_headers.NumberOfSymbols = (symbol_tables.len() / 0x12) as u32;
-        file_headers.PointerToSymbolTable = p_data as u32;
-        // * Writing
-        // file header
-        let pfh = as_u8_slice(&file_headers);
-        writer.write_all(pfh).expect("");
-        // sections
-        for sh_one in &section_headers{
-            writer.write_all(as_u8_slice(sh_one)).expect("");
-        }
-        // data
-        let mut seciter = sections.iter();
-        while let Some(sec) = seciter.next(){
-            writer.write_all(&sec.data).expect("");
-        }
-        // symbols
-        writer.write_all(symbol_tables.as_bytes()).expect("");
-        writer.flush().expect("");
+        for reloc in sections.iter().flat_map(|sec| &sec.relocations){
+            if module.target(reloc.symbol).is_none(){
+                errors.push(ae.diagnostic(reloc.symbol, Code::UndefinedSymbol, "Undefined symbol."));
+            }
+        }
+        if !errors.is_empty(){
+            return Err(errors);
+        }
+        let mut out = Vec::new();
+        format.write(&module, &mut out).map_err(|diagnostic| vec![diagnostic])?;
+        Ok(out)
     }
 
-    fn label_or_instruction(&self, mut input: & 'a str) -> & 'a str{
+    fn label_or_instruction(&self, mut input: & 'a str) -> Result<& 'a str, Diagnostic>{
+        if let Some(idx) = self.current.get().checked_sub(1){
+            self.here.set(self.sections.borrow()[idx].size());
+        }
 // label or instruction
         let Ok((s, first_word)) = get_word(input) else{
-            return input;
+            let ae = self.asm_error();
+            return ae.error_from_word(input, Code::Syntax, "Syntax Error.");
         };
         input = s;
+        // name equ expr
+        if let Some(s) = self.read_equ(input){
+            return self.equ(first_word, s);
+        }
         let c = get_str_first(input);
         // label
         if c == b':'{
+            if let Some(s) = self.read_equ(&input[1..]){
+                return self.equ(first_word, s);
+            }
             if let Ok((s, _)) = read_chars(input, 1){
-                let mut labels = self.labels.borrow_mut();
-                let sections = self.sections.borrow();
-                let section = &sections[sections.len() - 1];
-                labels.push(Label::new(first_word,
-                    section.data.len(), sections.len()));
+                self.define_label(first_word)?;
                 input = s;
-                return input;
+                return Ok(input);
             }else{
-                let ae = AsmError::new(self.m_contents);
-                ae.panic_from_word(input, "colon error");
+                let ae = self.asm_error();
+                return ae.error_from_word(input, Code::Colon, "colon error");
             };
         }
+        // label without a colon, alone or before an instruction
+        if !is_mnemonic(first_word){
+            let s = self.ignore_space(input);
+            if s.is_empty() || is_ignore_comment(s){
+                self.define_label(first_word)?;
+                self.warn("label-orphan", first_word, "Label alone on a line without a colon might be in error.");
+                return Ok(s);
+            }
+            if get_word(s).is_ok_and(|(_, word)| is_mnemonic(word)){
+                self.define_label(first_word)?;
+                return Ok(s);
+            }
+        }
         // instruction
         input = self.ignore_space(input);
-        input = self._instruction(input, first_word);
-        input
+        self._instruction(input, first_word)
     }
-    fn _instruction(&self, mut input: & 'a str, instruction: & 'a str) -> & 'a str{
-        let first_word_lower = instruction.to_lowercase();
-        let instruction_lower = first_word_lower.as_str();
-        // dx or resx
-        let c = get_str_first(instruction_lower);
-        // Declaring Uninitialized or Initialized Data
-        match c {
-            //dx
-            b'd' =>{
-                if instruction_lower.len() == 2{
-                    let c = instruction_lower.as_bytes()[1];
-                    let size = self.dx_to_size(c);
-                    input = self.dx(input, size);
-                    return input;
-                }
-            },
-            //resx
-            b'r' =>{
-                if instruction_lower.len() == 4 && &instruction_lower[0..3] == "res" {
-                    let c = instruction_lower.as_bytes()[3];
-                    let size = self.dx_to_size(c);
-                    input = self.resx(input, size);
-                    return input;
+    fn define_label(&self, name: & 'a str) -> Result<(), Diagnostic>{
+        let (pos, section_number) = (self.section()?.size(), self.current.get());
+        let mut labels = self.labels.borrow_mut();
+        let pass = self.pass.get();
+        let ae = self.asm_error();
+        if let Some((first, _, _)) = self.equs.borrow().iter().find(|(n, _, _)| *n == name){
+            return Err(ae.diagnostic(name, Code::SymbolRedefined, "Symbol redefined.").label(ae.span_of(first), "defined here by equ"));
+        }
+        self.unreachable.set(None);
+        if let Some(label) = labels.iter_mut().find(|l| l.name == name){
+            if label.pass == pass{
+                // harmless at the same place
+                if label.pos == pos && label.section_number == section_number{
+                    let message = format!("Label {} is defined twice.", name);
+                    let warning = Diagnostic::warning("label-redef", &message).label(ae.span_of(name), "");
+                    self.warnings.borrow_mut().push(warning.label(ae.span_of(label.name), "first defined here"));
+                    return Ok(());
                 }
+                return Err(ae.diagnostic(name, Code::LabelRedefined, "Label redefined.").label(ae.span_of(label.name), "first defined here"));
+            }
+            if label.pos != pos || label.section_number != section_number{
+                self.changed.set(true);
             }
-            _ => {}
+            label.pos = pos;
+            label.section_number = section_number;
+            label.pass = pass;
+        }else{
+            labels.push(Label::new(name, pos, section_number, pass));
+            self.changed.set(true);
+        }
+        Ok(())
+    }
+    // (section number, position)
+    // a section name is the start of the section ($$)
+    fn find_label(&self, name: &str) -> Option<(usize, usize)>{
+        let labels = self.labels.borrow();
+        if let Some(l) = labels.iter().find(|l| l.name == name){
+            return Some((l.section_number, l.pos));
+        }
+        let sections = self.sections.borrow();
+        sections.iter().position(|sec| sec.name == name).map(|idx| (idx + 1, 0))
+    }
+    // rel8 is chosen until the target turns out to be too far.
+    // a branch never shrinks again, so the passes converge
+    fn branch_short(&self, target: Expr, short_len: usize) -> Result<bool, Diagnostic>{
+        let idx = self.branch_idx.get();
+        self.branch_idx.set(idx + 1);
+        let mut long_branches = self.long_branches.borrow_mut();
+        if idx >= long_branches.len(){
+            long_branches.push(false);
+        }
+        if long_branches[idx]{
+            return Ok(false);
+        }
+        let short = match self.branch_rel(target, short_len)?{
+            Some(rel) => (-0x80..0x80).contains(&rel),
+            // maybe defined later
+            None => self.pass.get() == 1 && target.label.is_some_and(|label| self.find_label(label).is_none()),
         };
+        if !short{
+            long_branches[idx] = true;
+        }
+        Ok(short)
+    }
+    // displacement to label + addend in this section
+    fn branch_rel(&self, target: Expr, len: usize) -> Result<Option<i64>, Diagnostic>{
+        let here = self.section()?.size() + len;
+        let Some(label) = target.label else{
+            return Ok(None);
+        };
+        match self.find_label(label){
+            Some((section_number, pos)) if section_number == self.current.get() =>{
+                Ok(Some(pos as i64 + target.value - here as i64))
+            },
+            _ => Ok(None),
+        }
+    }
+    fn _instruction(&self, mut input: & 'a str, instruction: & 'a str) -> Result<& 'a str, Diagnostic>{
+        let first_word_lower = instruction.to_lowercase();
+        let instruction_lower = first_word_lower.as_str();
+        // Declaring Uninitialized or Initialized Data
+        match data_directive(instruction_lower){
+            Some((false, size)) => return self.dx(input, size),
+            Some((true, size)) => return self.resx(input, size),
+            None =>{},
+        }
+        if !DIRECTIVES.contains(&instruction_lower){
+            self.reachable(instruction);
+        }
         match instruction_lower{
-            "section" =>{
-                input = self.section(input);
+            "section" | "segment" =>{
+                input = self.section_directive(input)?;
             },
-            "mov" =>{
-                input = self.mov(input);
+            "default" =>{
+                input = self.default_mode(input)?;
             },
-            "ret" =>{
-                input = self.ret(input);
+            "org" =>{
+                input = self.org(input)?;
             },
-            "add" =>{
-                input = self.add(input);
+            "global" =>{
+                let (names, s) = self.read_names(input)?;
+                self.globals.borrow_mut().extend(names);
+                input = s;
+            },
+            "extern" =>{
+                let (names, s) = self.read_names(input)?;
+                self.externs.borrow_mut().extend(names);
+                input = s;
+            },
+            "common" =>{
+                input = self.common(input)?;
+            },
+            "times" =>{
+                input = self.times(input)?;
+            },
+            "align" =>{
+                input = self.align(input, false)?;
+            },
+            "alignb" =>{
+                input = self.align(input, true)?;
+            },
+            "incbin" =>{
+                input = self.incbin(input)?;
+            },
+            "rep" | "repe" | "repz" | "repne" | "repnz" | "lock" =>{
+                input = self.prefix(input, instruction_lower)?;
+            },
+            "jmp" =>{
+                // eb cb | e9 cd | ff /4
+                input = self.branch(input, &[0xEB], &[0xE9], "jmp")?;
+            },
+            "call" =>{
+                // e8 cd | ff /2
+                input = self.branch(input, &[], &[0xE8], "call")?;
+            },
+            "loop" =>{
+                input = self.short_branch(input, &[0xE2])?;
+            },
+            "loope" | "loopz" =>{
+                input = self.short_branch(input, &[0xE1])?;
+            },
+            "loopne" | "loopnz" =>{
+                input = self.short_branch(input, &[0xE0])?;
+            },
+            "jrcxz" =>{
+                input = self.short_branch(input, &[0xE3])?;
+            },
+            "jecxz" =>{
+                input = self.short_branch(input, &[0x67, 0xE3])?;
+            },
+            _ if insn::exists(instruction_lower) =>{
+                input = self.instruction(input, instruction_lower)?;
+            },
+            _ if instruction_lower.starts_with('j') && condition(&instruction_lower[1..]).is_some() =>{
+                // 70+cc cb | 0f 80+cc cd
+                let cc = condition(&instruction_lower[1..]).unwrap();
+                input = self.branch(input, &[0x70 | cc], &[0x0F, 0x80 | cc], instruction_lower)?;
+            },
+            _ if UNSUPPORTED.contains(&instruction_lower) =>{
+                let ae = self.asm_error();
+                return ae.error_from_word(instruction, Code::UnsupportedInstruction, "Instruction is not supported.");
             },
-            "sub" => {
-                input = self.sub(input);
-            }
             _ =>{
-                let ae = AsmError::new(self.m_contents);
-                ae.panic_from_word(input, "Syntax Error.");
+                let ae = self.asm_error();
+                return ae.error_from_word(input, Code::Syntax, "Syntax Error.");
             }
         };
-        input
+        if instruction_lower == "jmp" || instruction_lower == "ret"{
+            self.unreachable.set(Some(instruction));
+        }
+        Ok(input)
+    }
+    // rep, repe, repne or lock, then the instruction
+    fn prefix(&self, input: & 'a str, prefix: &str) -> Result<& 'a str, Diagnostic>{
+        let Ok((s, instruction)) = get_word(input) else{
+            let ae = self.asm_error();
+            return ae.error_from_word(input, Code::RequireInstruction, "Require Instruction.");
+        };
+        let byte = match prefix{
+            "lock" => 0xF0,
+            "repne" | "repnz" => 0xF2,
+            _ => 0xF3,
+        };
+        self.emit(vec![byte], None)?;
+        self._instruction(self.ignore_space(s), instruction)
     }
-    fn dx_to_size(&self, c: u8) -> u8{
-        match c{
-            b'b'=>1,
-            b'w'=>2,
-            b'd'=>4,
-            b'q'=>8,
-            b't'=>16,
-            b'o'=>32,
-            b'y'=>64,
-            b'z'=>128,
-            _ => {
-                0
-            }
+    // an instruction after ret or jmp is never run without a label between
+    fn reachable(&self, word: & 'a str){
+        if let Some(end) = self.unreachable.take(){
+            let ae = self.asm_error();
+            let warning = Diagnostic::warning("unreachable", "Unreachable code.").label(ae.span_of(word), "");
+            self.warnings.borrow_mut().push(warning.label(ae.span_of(end), "nothing comes back after this"));
         }
     }
-    fn resx(&self, mut input: & 'a str, size: u8 ) -> & 'a str{
+    fn resx(&self, mut input: & 'a str, size: u8 ) -> Result<& 'a str, Diagnostic>{
         if input == ""{
-            return input;
+            return Ok(input);
         }
         if let Ok((s, _)) = ignore_space(input){
             input = s;
         }
         if is_ignore_comment(input) {
-            return input;
+            return Ok(input);
         }
-        let c = get_str_first(input);
-        if let Ok((s, fig)) = get_figure(input){
-            input = s;
-            if let Ok(fig) = fig.parse::<u64>(){
-                let mut sections = self.sections.borrow_mut();
-                let section = sections.last_mut().expect("");
-                let mut data = iter::repeat(0u8)
-                    .take(size as usize * fig as usize)
-                    .collect::<Vec<u8>>();
-                section.data.append(&mut data);
-            }
+        if !Asm::is_expr(input){
+            let ae = self.asm_error();
+            return ae.error_from_word(input, Code::RequireFigure, "Require Figure.");
+        }
+        let word = input;
+        let (s, count) = self.read_expr(input)?;
+        input = s;
+        let count = self.absolute(count, word);
+        if count < 0{
+            let ae = self.asm_error();
+            return ae.error_from_word(word, Code::NegativeCount, "Negative count.");
+        }
+        let size = size as usize * count as usize;
+        let mut section = self.section_mut()?;
+        if section.is_nobits(){
+            section.reserved += size;
+        }else{
+            section.data.extend(iter::repeat_n(0u8, size));
+        }
+        Ok(input)
+    }
+    // times count instruction
+    fn times(&self, input: & 'a str) -> Result<& 'a str, Diagnostic>{
+        let ae = self.asm_error();
+        if !Asm::is_expr(input){
+            return ae.error_from_word(input, Code::RequireFigure, "Require Figure.");
+        }
+        let (s, count) = self.read_expr(input)?;
+        let count = self.absolute(count, input);
+        if count < 0{
+            return ae.error_from_word(input, Code::NegativeCount, "Negative count.");
+        }
+        self.repeat(self.ignore_space(s), count as usize)
+    }
+    // assemble the instruction count times, $ moves each time
+    fn repeat(&self, input: & 'a str, count: usize) -> Result<& 'a str, Diagnostic>{
+        let Ok((s, instruction)) = get_word(input) else{
+            let ae = self.asm_error();
+            return ae.error_from_word(input, Code::RequireInstruction, "Require Instruction.");
+        };
+        let s = self.ignore_space(s);
+        let mut rest = &input[input.len()..];
+        for _ in 0..count{
+            self.here.set(self.section()?.size());
+            rest = self._instruction(s, instruction)?;
+        }
+        // nothing may follow the instruction
+        let rest = self.ignore_space(rest);
+        if !rest.is_empty() && !is_ignore_comment(rest){
+            let ae = self.asm_error();
+            return ae.error_from_word(rest, Code::Syntax, "Syntax Error.");
+        }
+        Ok(rest)
+    }
+    // align n [, instruction] | alignb n [, resb 1]
+    // nops in code, zeros in data and reserved space in nobits
+    fn align(&self, input: & 'a str, nobits: bool) -> Result<& 'a str, Diagnostic>{
+        let ae = self.asm_error();
+        let (s, align) = self.read_figure(input)?;
+        if !align.is_power_of_two(){
+            return ae.error_from_word(input, Code::InvalidAlignment, "Alignment must be a power of 2.");
+        }
+        let (pad, exec) ={
+            let mut section = self.section_mut()?;
+            // the section is aligned at least as much
+            let alignment = section.alignment().max(align);
+            section.align = Some(alignment);
+            ((align - section.size() as u64 % align) % align, section.is_exec())
+        };
+        if let Some(s) = s.strip_prefix(','){
+            return self.repeat(self.ignore_space(s), pad as usize);
+        }
+        let mut section = self.section_mut()?;
+        if section.is_nobits(){
+            section.reserved += pad as usize;
+        }else if nobits || !exec{
+            section.data.extend(iter::repeat_n(0u8, pad as usize));
         }else{
-            let ae = AsmError::new(self.m_contents);
-            let mes = format!("Require Figure.");
-            ae.panic_from_word_idx(input, 0, mes.as_str());
+            drop(section);
+            self.emit(nops(pad as usize), None)?;
         }
-        input
+        Ok(s)
     }
-    fn dx(&self, mut input: & 'a str, size: u8) -> & 'a str{
+    fn dx(&self, mut input: & 'a str, size: u8) -> Result<& 'a str, Diagnostic>{
         if input == ""{
-            return input;
+            return Ok(input);
         }
         if let Ok((s, _)) = ignore_space(input){
             input = s;
         }
         if is_ignore_comment(input){
-            return input;
+            return Ok(input);
         }
-        let c = get_str_first(input);
-        // string
-        if c == b'\'' || c ==b'\"' {
-            if let Ok((s, first)) = get_string(input){
-                input = s;
-                let len = first.len() % size as usize;
-
-                let mut sections = self.sections.borrow_mut();
-                let section = sections.last_mut().expect("");
-
-                section.data.append(& mut first.as_bytes().to_vec());
-                let mut zeros = vec![0u8; len];
-                section.data.append(& mut zeros);
-
-            }else {
-                let ae = AsmError::new(self.m_contents);
-                let mes = format!("Require \'or\".");
-                ae.panic_from_word_idx(input, 0, mes.as_str());
-            }
-            // figure
-        }else if let Ok((s, first)) = get_figure(input){
+        // string, unless it is a character constant in an expression
+        if let Some(s) = get_quoted(input).ok().filter(|(s, _)| is_item_end(self.ignore_space(s))){
+            let (s, mut first) = s;
             input = s;
-            if let Ok(figure) = first.parse::<u64>(){
-
-                let mut sections = self.sections.borrow_mut();
-                let section = sections.last_mut().expect("");
-
-                section.data.append(& mut as_u8_slice_size(&figure, size as usize).to_vec());
-            }else{}// not occur
+            // zero fill up to the next item
+            first.resize(first.len().next_multiple_of(size as usize), 0);
+            self.emit(first, None)?;
+        }else if let Some((s, mut data)) = self.dx_data(input, size)?{
+            input = s;
+            data.resize(data.len().next_multiple_of(size as usize), 0);
+            self.emit(data, None)?;
+        }else if Asm::is_expr(input){
+            input = self.dx_value(input, size)?;
         }else{
-            let ae = AsmError::new(self.m_contents);
+            let ae = self.asm_error();
             let mes = format!("Require {}.", input);
-            ae.panic_from_word_idx(input, 0, mes.as_str());
+            return ae.error_from_word(input, Code::RequireData, mes.as_str());
         }
         input = self.ignore_space(input);
         let c = get_str_first(input);
         if c == b','{
-            input = self.read_comma(input);
-            input = self.dx(input, size);
+            input = self.read_comma(input)?;
+            input = self.dx(input, size)?;
         }
-        input
+        Ok(input)
     }
-    fn section(&self, mut input: & 'a str) -> & 'a str{
-        input = self.ignore_space(input);
-        if let Ok((s, section_name)) = get_word(input){
-            let mut sections = self.sections.borrow_mut();
-            sections.push(Section::new(section_name));
-            s
-        }else{
-            let ae = AsmError::new(self.m_contents);
-            ae.panic_from_word(input, "Syntax Error.");
-            panic!();
+    // [seg] expr [wrt ..imagebase | ..secrel]
+    fn dx_value(&self, mut input: & 'a str, size: u8) -> Result<& 'a str, Diagnostic>{
+        let ae = self.asm_error();
+        let first = input;
+        let mut seg = false;
+        if let Ok((s, word)) = get_word(input){
+            if word.eq_ignore_ascii_case("seg"){
+                input = self.ignore_space(s);
+                seg = true;
+            }
         }
+        let mut kind = match size{
+            _ if seg => Some(RelocKind::Section),
+            8 => Some(RelocKind::Addr64),
+            4 => Some(RelocKind::Addr32),
+            2 => Some(RelocKind::Addr16),
+            _ => None,
+        };
+        let (s, expr) = self.read_expr(input)?;
+        input = self.ignore_space(s);
+        if let Ok((s, word)) = get_word(input){
+            if !word.eq_ignore_ascii_case("wrt"){
+                return ae.error_from_word(input, Code::RequireWrt, "Require wrt.");
+            }
+            input = self.ignore_space(s);
+            let Ok((s, wrt)) = get_word(input) else{
+                return ae.error_from_word(input, Code::RequireSpecial, "Require ..imagebase or ..secrel.");
+            };
+            kind = match wrt.to_lowercase().as_str(){
+                "..imagebase" => Some(RelocKind::Addr32Nb),
+                "..secrel" => Some(RelocKind::SecRel),
+                _ =>{
+                    return ae.error_from_word(input, Code::RequireSpecial, "Require ..imagebase or ..secrel.");
+                }
+            };
+            input = s;
+        }else if expr.label.is_none() && !seg{
+            // absolute, sign extended beyond 8 bytes
+            if !encode::fits(expr.value as u64, size){
+                let name = match size {1 => "Byte", 2 => "Word", _ => "Dword"};
+                self.warn("number-overflow", first, &format!("{} data exceeds bounds.", name));
+            }else if size > 8 && expr.value < 0{
+                self.warn("sign-extend", first, &format!("Negative figure is sign extended to {} bytes.", size));
+            }
+            let fill = if expr.value < 0 {0xFF} else {0};
+            let mut data = expr.value.to_le_bytes().to_vec();
+            data.resize(size as usize, fill);
+            self.emit(data, None)?;
+            return Ok(input);
+        }
+        let Some(label) = expr.label else{
+            return ae.error_from_word(first, Code::RequireLabel, "Require Label.");
+        };
+        let Some(kind) = kind.filter(|kind| kind.size() == size as usize) else{
+            let mes = format!("Can't relocate {}-byte data.", size);
+            return ae.error_from_word(first, Code::RelocationSize, mes.as_str());
+        };
+        let data = expr.value.to_le_bytes()[..size as usize].to_vec();
+        self.emit(data, Some(Relocation::new(0, label, kind)))?;
+        Ok(input)
     }
-    fn ret(&self, input: & 'a str)-> & 'a str {
-        let mut sections = self.sections.borrow_mut();
-        let section = sections.last_mut().expect("");
-        section.data.push(0xC3);
-        input
+    // wrt ..name
+    fn read_wrt(&self, input: & 'a str, name: &str) -> Result<Option<& 'a str>, Diagnostic>{
+        let Ok((s, word)) = get_word(input) else{
+            return Ok(None);
+        };
+        if !word.eq_ignore_ascii_case("wrt"){
+            return Ok(None);
+        }
+        let s = self.ignore_space(s);
+        let Ok((s, wrt)) = get_word(s) else{
+            return Ok(None);
+        };
+        if !wrt.eq_ignore_ascii_case(name){
+            let ae = self.asm_error();
+            return ae.error_from_word(s, Code::RequireSpecial, format!("Require {}.", name).as_str());
+        }
+        Ok(Some(s))
+    }
+    fn section(&self) -> Result<Ref<'_, Section<'a>>, Diagnostic>{
+        let idx = self.current_index()?;
+        Ok(Ref::map(self.sections.borrow(), |sections| &sections[idx]))
+    }
+    fn section_mut(&self) -> Result<RefMut<'_, Section<'a>>, Diagnostic>{
+        let idx = self.current_index()?;
+        Ok(RefMut::map(self.sections.borrow_mut(), |sections| &mut sections[idx]))
+    }
+    // the current section, which must not be nobits
+    fn initialized_section(&self) -> Result<RefMut<'_, Section<'a>>, Diagnostic>{
+        let section = self.section_mut()?;
+        if section.is_nobits(){
+            let ae = self.asm_error();
+            return ae.error_from_word(self.line.get(), Code::NobitsData, "Initialized data in nobits section.");
+        }
+        Ok(section)
+    }
+    fn current_index(&self) -> Result<usize, Diagnostic>{
+        let Some(idx) = self.current.get().checked_sub(1) else{
+            let ae = self.asm_error();
+            return ae.error_from_word(self.line.get(), Code::NoSection, "No section.");
+        };
+        Ok(idx)
     }
-    fn add(&self, mut input: & 'a str) -> & 'a str{
-        let ae = AsmError::new(self.m_contents);
-        let ((value1, value1str, value2, value2str), s) = self.read_2args(input);
+    // section name [code|data|bss|rdata|info] [exec] [write] [nobits]
+    //     [align=n] [start=n] [follows=name]
+    // an existing section is reopened
+    fn section_directive(&self, mut input: & 'a str) -> Result<& 'a str, Diagnostic>{
+        let ae = self.asm_error();
+        input = self.ignore_space(input);
+        let Ok((s, section_name)) = get_section_name(input) else{
+            return ae.error_from_word(input, Code::Syntax, "Syntax Error.");
+        };
         input = s;
-
-        let mut data = Vec::<u8>::new();
-        match value1{
-            Value::Reg(reg1, size1) => {
-                match value2{
-                    Value::Figure(v2) =>{
-                        if size1 == 8{
-                            let rexb = (reg1 & 0b1000) >> 3 << r::REX_B;
-                            let v2 = v2.parse::<u64>().unwrap();
-                            let v2size = if v2 < 0x80 || v2 >= 0xffffffff_ffffff80 {1}
-                                         else if v2 < 0x80000000 || v2 >= 0xffffffff_80000000 {4}
-                                         else {8};
-                            if v2size == 8{
-                                ae.panic_from_word(value2str, "Expect signed 32bit");
-                            }
-                            let v2 = v2.to_le_bytes().to_vec();
-
-                            let rexw: u8 = 0x48 | rexb;
-                            data.push(rexw);
-                            let reg = reg1 & 0b0111;
-                            if v2size == 1{
-                                let op: u8 = 0x83;
-                                let modf = 0b11;
-                                let regf = 0b000;
-                                let rmf = reg1 & 0b0111;
-
-                                let regrm = r::create_modrm(modf, regf, rmf);
-
-                                data.append(&mut vec![op, regrm]);
-                                data.push(v2[0]);
-                            }else if reg == reg::RAX{
-                                let op:u8 = 0x05;
-                                data.push(op);
-                                data.extend(&v2[0..v2size]);
-                            }else{
-                                let op = 0x81;
-                                let modf = 0b11;
-                                let regf = 0b000;
-                                let rmf = reg1 & 0b0111;
-
-                                let regrm = r::create_modrm(modf, regf, rmf);
-
-                                data.extend([op, regrm]);
-                                data.extend(&v2[0..v2size]);
-                            }
-                        }
-                    },
-                    Value::Reg(reg2, size2) =>{
-                        // r/m reg1
-                        let rexb: u8 = (reg1 & 0b1000) >> 3;
-                        // reg reg2
-                        let rexr: u8 = (reg2 & 0b1000) >> 3;
-                        
-                        if size1== 8 {
-                            let rex = r::create_rex(1, rexr, 0, rexb);
-                            data.push(rex);
-                        }else if rexb == (0b1 << r::REX_B) || rexr == (0b1 << r::REX_R) {
-                            let rex = r::create_rex(0, rexr, 0, rexb);
-                            data.push(rex);
-                        }
-                        let op = 0x01;
-                        let reg1 = reg1 & 0b0111;
-                        let reg2 = reg2 & 0b0111;
-                        let modrm = r::create_modrm(0b11, reg2, reg1);
-                        data.push(op);
-                        data.push( modrm);
-                    },
-                    _ =>{ae.panic_from_word(value2str, "Not")}
-                }
+        self.unreachable.set(None);
+        let mut sections = self.sections.borrow_mut();
+        let idx = match sections.iter().position(|sec| sec.name == section_name){
+            Some(idx) => idx,
+            None =>{
+                sections.push(Section::new(section_name, self.other_section));
+                sections.len() - 1
             },
-            _ =>{ae.panic_from_word(value1str, "Not")}
+        };
+        self.current.set(idx + 1);
+        let section = &mut sections[idx];
+        loop{
+            input = self.ignore_space(input);
+            let Ok((s, attr)) = get_word(input) else{
+                break;
+            };
+            // flags
+            let matched = match attr.to_lowercase().as_str(){
+                "code" | "text" =>{section.kind = SectionKind::Code; true},
+                "data" =>{section.kind = SectionKind::Data; true},
+                "bss" | "nobits" =>{section.kind = SectionKind::Bss; true},
+                "rdata" =>{section.kind = SectionKind::Rdata; true},
+                "info" =>{section.kind = SectionKind::Info; true},
+                "progbits" =>{
+                    if section.is_nobits(){
+                        section.kind = SectionKind::Data;
+                    }
+                    true
+                },
+                "exec" =>{section.exec = Some(true); true},
+                "noexec" =>{section.exec = Some(false); true},
+                "write" =>{section.write = Some(true); true},
+                "nowrite" =>{section.write = Some(false); true},
+                _ => false,
+            };
+            if matched{
+                input = s;
+                continue;
+            }
+            // key=value
+            let Some(s) = s.strip_prefix('=') else{
+                return ae.error_from_word(input, Code::UnknownAttribute, "Unknown section attribute.");
+            };
+            match attr.to_lowercase().as_str(){
+                "align" =>{
+                    let (s, align) = self.read_figure(s)?;
+                    if !align.is_power_of_two(){
+                        return ae.error_from_word(input, Code::InvalidAlignment, "Alignment must be a power of 2.");
+                    }
+                    section.align = Some(align);
+                    input = s;
+                },
+                "start" =>{
+                    let (s, start) = self.read_figure(s)?;
+                    section.start = Some(start);
+                    input = s;
+                },
+                "follows" =>{
+                    let Ok((s, name)) = get_section_name(s) else{
+                        return ae.error_from_word(s, Code::RequireSection, "Require Section.");
+                    };
+                    section.follows = Some(name);
+                    input = s;
+                },
+                _ =>{
+                    return ae.error_from_word(input, Code::UnknownAttribute, "Unknown section attribute.");
+                }
+            }
+        }
+        if section.start.is_some() && section.follows.is_some(){
+            return ae.error_from_word(section_name, Code::StartFollows, "Can't use start= with follows=.");
+        }
+        Ok(input)
+    }
+    // name, name, ...
+    fn read_names(&self, mut input: & 'a str) -> Result<(Vec<& 'a str>, & 'a str), Diagnostic>{
+        let mut names = Vec::new();
+        loop{
+            input = self.ignore_space(input);
+            let Ok((s, name)) = get_word(input) else{
+                let ae = self.asm_error();
+                return ae.error_from_word(input, Code::RequireSymbol, "Require Symbol.");
+            };
+            names.push(name);
+            input = self.ignore_space(s);
+            if !input.starts_with(','){
+                break;
+            }
+            input = self.read_comma(input)?;
+        }
+        Ok((names, input))
+    }
+    // common name size[:align]
+    fn common(&self, mut input: & 'a str) -> Result<& 'a str, Diagnostic>{
+        input = self.ignore_space(input);
+        let Ok((s, name)) = get_word(input) else{
+            let ae = self.asm_error();
+            return ae.error_from_word(input, Code::RequireSymbol, "Require Symbol.");
+        };
+        let (s, size) = self.read_figure(s)?;
+        let (s, align) = match s.strip_prefix(':'){
+            Some(s) => self.read_figure(s)?,
+            None => (s, 1),
+        };
+        self.commons.borrow_mut().push((name, size, align));
+        Ok(s)
+    }
+    // org address
+    fn org(&self, input: & 'a str) -> Result<& 'a str, Diagnostic>{
+        let (s, org) = self.read_figure(input)?;
+        self.org.set(org);
+        Ok(s)
+    }
+    // absolute expression which must be known now
+    fn read_figure(&self, input: & 'a str) -> Result<(& 'a str, u64), Diagnostic>{
+        let ae = self.asm_error();
+        let input = self.ignore_space(input);
+        if !Asm::is_expr(input){
+            return ae.error_from_word(input, Code::RequireFigure, "Require Figure.");
+        }
+        let (s, expr) = self.read_expr(input)?;
+        if expr.label.is_some(){
+            return ae.error_from_word(input, Code::NotAbsolute, "Expression is not absolute.");
+        }
+        Ok((self.ignore_space(s), expr.value as u64))
+    }
+    // default rel | abs
+    fn default_mode(&self, mut input: & 'a str) -> Result<& 'a str, Diagnostic>{
+        input = self.ignore_space(input);
+        let Ok((s, word)) = get_word(input) else{
+            let ae = self.asm_error();
+            return ae.error_from_word(input, Code::RequireMode, "Require rel or abs.");
+        };
+        match word.to_lowercase().as_str(){
+            "rel" => self.default_rel.set(true),
+            "abs" => self.default_rel.set(false),
+            _ =>{
+                let ae = self.asm_error();
+                return ae.error_from_word(input, Code::RequireMode, "Require rel or abs.");
+            }
+        }
+        Ok(s)
+    }
+    fn emit(&self, mut data: Vec<u8>, reloc: Option<Relocation<'a>>) -> Result<(), Diagnostic>{
+        let section_number = self.current.get();
+        let target = reloc.as_ref().and_then(|reloc| self.find_label(reloc.symbol));
+        let mut section = self.initialized_section()?;
+        if let Some(mut reloc) = reloc{
+            // rip points after the immediate
+            let rip = data.len();
+            if let RelocKind::GotPcRel(_) = reloc.kind{
+                reloc.kind = RelocKind::GotPcRel((rip - reloc.offset - 4) as u8);
+            }
+            if let RelocKind::Rel32(_) = reloc.kind{
+                reloc.kind = RelocKind::Rel32((rip - reloc.offset - 4) as u8);
+                // resolved without a relocation in the same section
+                if let Some((target_section, pos)) = target{
+                    if target_section == section_number{
+                        let field = &mut data[reloc.offset..reloc.offset + 4];
+                        let addend = i32::from_le_bytes(field.try_into().unwrap()) as i64;
+                        let rel = pos as i64 + addend - (section.data.len() + rip) as i64;
+                        field.copy_from_slice(&(rel as i32).to_le_bytes());
+                        section.data.append(&mut data);
+                        return Ok(());
+                    }
+                }
+            }
+            reloc.offset += section.data.len();
+            section.relocations.push(reloc);
         }
-        let mut sections = self.sections.borrow_mut();
-        let section = sections.last_mut().expect("");
-        // data
         section.data.append(&mut data);
-        input
+        Ok(())
     }
-    fn sub(&self, mut input: & 'a str) -> & 'a str{
-        let ae = AsmError::new(self.m_contents);
-        let ((value1, value1str, value2, value2str), s) = self.read_2args(input);
+    // [short | near] label, reg or [mem]
+    // short_op: rel8, near_op: rel32, name: indirect form in the database
+    fn branch(&self, mut input: & 'a str, short_op: &[u8], near_op: &[u8], name: &str) -> Result<& 'a str, Diagnostic>{
+        let ae = self.asm_error();
+        let mut short = None;
+        if let Ok((s, word)) = get_word(input){
+            match word.to_lowercase().as_str(){
+                "short" if !short_op.is_empty() =>{
+                    short = Some(true);
+                    input = self.ignore_space(s);
+                },
+                "near" =>{
+                    short = Some(false);
+                    input = self.ignore_space(s);
+                },
+                _ =>{},
+            }
+        }
+        let value_str = input;
+        let (s, value) = self.read_value_unwrap(input, "Expect Label, Register or Memory.")?;
         input = s;
-        let mut data = Vec::<u8>::new();
-        match value1{
-            Value::Reg(reg1, size1) => {
-                match value2{
-                    Value::Figure(v2) => {
-                        if size1 == 8{
-                            let rexb = (reg1 & 0b1000) >> 3 << r::REX_B;
-                            let v2 = v2.parse::<u64>().unwrap();
-                            let v2size = if v2 < 0x80 || v2 >= 0xffffffff_ffffff80 {1}
-                                         else if v2 < 0x80000000 || v2 >= 0xffffffff_80000000 {4}
-                                         else {8};
-                            if v2size == 8{
-                                ae.panic_from_word(value2str, "Expect signed 32bit");
-                            }
-                            let v2 = v2.to_le_bytes().to_vec();
-
-                            let rexw: u8 = 0x48 | rexb;
-                            data.push(rexw);
-                            let reg = reg1 & 0b0111;
-                            if v2size == 1{
-                                let op: u8 = 0x83;
-                                let modf = 0b11;
-                                let regf = 0b101;// /5
-                                let rmf = reg1 & 0b0111;
-
-                                let regrm = r::create_modrm(modf, regf, rmf);
-
-                                data.append(&mut vec![op, regrm]);
-                                data.push(v2[0]);
-                            }else if reg == reg::RAX{
-                                let op:u8 = 0x2d;
-                                data.push(op);
-                                data.extend(&v2[0..v2size]);
-                            }else{
-                                let op = 0x81;
-                                let modf = 0b11;
-                                let regf = 0b101;// /5
-                                let rmf = reg1 & 0b0111;
-
-                                let regrm = r::create_modrm(modf, regf, rmf);
-
-                                data.extend([op, regrm]);
-                                data.extend(&v2[0..v2size]);
-                            }
-                        }
-                    },
-                    Value::Reg(reg2, size2) => {
-                        // r/m reg1
-                        let rexb: u8 = (reg1 & 0b1000) >> 3;
-                        // reg reg2
-                        let rexr: u8 = (reg2 & 0b1000) >> 3;
-                        
-                        if size1== 8 {
-                            let rex = r::create_rex(1, rexr, 0, rexb);
-                            data.push(rex);
-                        }else if rexb == (0b1 << r::REX_B) || rexr == (0b1 << r::REX_R) {
-                            let rex = r::create_rex(0, rexr, 0, rexb);
-                            data.push(rex);
-                        }
-                        let op = 0x29;
-                        let reg1 = reg1 & 0b0111;
-                        let reg2 = reg2 & 0b0111;
-                        let modrm = r::create_modrm(0b11, reg2, reg1);
-                        data.push(op);
-                        data.push( modrm);
-                    },
-                    _ => {ae.panic_from_word(value2str, "Not");}
+        // label wrt ..plt
+        let mut kind = RelocKind::Rel32(0);
+        if let Value::Imm(Expr{label: Some(_), ..}) = value{
+            if let Some(s) = self.read_wrt(self.ignore_space(input), "..plt")?{
+                kind = RelocKind::Plt32;
+                short = Some(false);
+                input = s;
+            }
+        }
+        match &value{
+            Value::Imm(target @ Expr{label: Some(label), ..}) =>{
+                let short = match short{
+                    Some(short) => short,
+                    None => !short_op.is_empty() && self.branch_short(*target, short_op.len() + 1)?,
+                };
+                if short{
+                    return self.short_branch(value_str, short_op);
+                }
+                let mut data = near_op.to_vec();
+                let offset = data.len();
+                data.extend((target.value as i32).to_le_bytes());
+                self.emit(data, Some(Relocation::new(offset, label, kind)))?;
+            },
+            Value::Reg(..) | Value::Mem(_) if insn::exists(name) =>{
+                self.encode_insn(name, &[value], value_str)?;
+            },
+            _ =>{
+                return ae.error_from_word(value_str, Code::RequireLabel, "Expect Label.");
+            }
+        }
+        Ok(input)
+    }
+    // rel8 only
+    fn short_branch(&self, input: & 'a str, op: &[u8]) -> Result<& 'a str, Diagnostic>{
+        let ae = self.asm_error();
+        if !Asm::is_expr(input){
+            return ae.error_from_word(input, Code::RequireLabel, "Require Label.");
+        }
+        let (s, target) = self.read_expr(input)?;
+        let Some(label) = target.label else{
+            return ae.error_from_word(input, Code::RequireLabel, "Require Label.");
+        };
+        let mut data = op.to_vec();
+        match self.branch_rel(target, data.len() + 1)?{
+            Some(rel) =>{
+                if !(-0x80..0x80).contains(&rel){
+                    self.defer_error(label, Code::ShortJumpRange, "Short jump is out of range.");
                 }
+                data.push(rel as u8);
+            },
+            None =>{
+                let defined = self.find_label(label).is_some()
+                    || self.externs.borrow().contains(&label) || self.commons.borrow().iter().any(|c| c.0 == label);
+                if self.pass.get() == 1{
+                    // the label may be defined later, or never
+                    self.changed.set(true);
+                }else if !defined{
+                    self.defer_error(label, Code::UndefinedSymbol, "Undefined symbol.");
+                }else{
+                    self.defer_error(label, Code::ShortJumpSection, "Short jump must be in the same section.");
+                }
+                data.push(0);
             },
-            _ => {ae.panic_from_word(value1str, "Not");}
         }
-        //########################################################################
-        let mut sections = self.sections.borrow_mut();
-        let section = sections.last_mut().expect("");
-        // data
-        section.data.append(&mut data);
-        input
+        self.emit(data, None)?;
+        Ok(s)
     }
-    fn mov(&self, mut input: & 'a str) -> & 'a str{
-        let ((value1, _, value2, _), s) = self.read_2args(input);
-        input = s;
-        let mut data = Vec::<u8>::new();
-        match value1{
-            Value::Reg(reg1, size) => {
-                match value2{
-                    Value::Figure(v2) =>{
-                        let rexb = (reg1 & 0b1000) >> 3;
-                        let v2 = v2.parse::<u64>().unwrap();
-                        // b8 + rd id
-                        let (op, v2size)= if v2 <= 0xffffffff {
-                            (0xb8, 4)
-                        // 48 c7 /0 id
-                        }else if v2 >= 0xffffffff_00000000{
-                            (0xc7, 4)
-                        // rex.w b8 + rd io
-                        }else{
-                            (0xb8, 8)
-                        };
-                        let v2 = v2.to_le_bytes().to_vec();
-                        if size == 8 && (op == 0xc7 || (op == 0xb8 && v2size == 8)) {
-                            let rexw: u8 = 0x48 | rexb;
-                            data.push(rexw);
-                        }else if rexb == 1{
-                            let rex:u8 = 0x40 | rexb;
-                            data.push(rex);
-                        }
-                        if op == 0xb8{
-                            let op = 0xB8 | (reg1 & 0b111);// opcode
-                            data.push(op);
-
-                            data.extend(&v2[0..v2size as usize]);
-                        }else if op == 0xc7{
-                            let op = 0xc7;
-                            let modf = 0b11;
-                            let regf = 0;
-                            let rmf = reg1 & 0b111;
-                            let modrm = modf << 6 | regf << 3 | rmf;
-                            data.push(op);
-                            data.push(modrm);
-                            data.extend(&v2[0..v2size as usize]);
-                        }else {panic!();}
-                        
-                    },
-                    Value::Reg(reg2, size) =>{
-                        //reg
-                        let rexr = (reg2 & 0b1000) >> 3 << r::REX_R;
-                        //rm
-                        let rexb = (reg1 & 0b1000) >> 3 << r::REX_B;
-                        if size == 8{
-                            let rex = 0x48 | rexr | rexb;
-                            data.push(rex);
-                        }else if rexr == 1 << r::REX_R || rexb == 1 << r::REX_B{
-                            let rex = 0x40 | rexr | rexb;
-                            data.push(rex);
+    // operands of an instruction in the database
+    fn instruction(&self, input: & 'a str, name: &str) -> Result<& 'a str, Diagnostic>{
+        let (values, s) = self.read_args(input)?;
+        self.encode_insn(name, &values, input)?;
+        Ok(s)
+    }
+    // the shortest encoding among the forms that accept the operands
+    fn encode_insn(&self, name: &str, values: &[Value<'a>], word: & 'a str) -> Result<(), Diagnostic>{
+        let ae = self.asm_error();
+        let mut best: Option<(Vec<u8>, Option<Relocation<'a>>)> = None;
+        let mut sign_extended = None;
+        let mut mem_sizes = Vec::<u8>::new();
+        let mut error = None;
+        for insn in insn::find(name){
+            for &size in insn.sizes{
+                let Some(mem_size) = insn::match_insn(insn, size, values) else{
+                    continue;
+                };
+                if let Some(mem_size) = mem_size{
+                    if !mem_sizes.contains(&mem_size){
+                        mem_sizes.push(mem_size);
+                    }
+                }
+                match insn::encode(insn, size, values){
+                    Ok(result) =>{
+                        if best.as_ref().is_none_or(|(data, _)| result.0.len() < data.len()){
+                            best = Some(result);
+                            sign_extended = insn::sign_extended(insn, size, values);
                         }
-                        let op = 0x89;
-                        let modf = 0b11;
-                        let reg = reg2 & 0b111;
-                        let rm = reg1 & 0b111;
-
-                        let r = r::create_modrm(modf, reg, rm);
-
-                        data.extend([op, r]);
-
                     },
-                    _ => {panic!()}
+                    Err(mes) => error = Some(mes),
                 }
-                
-            },
-            _ =>{}
-        };
-        //########################################################################
-        let mut sections = self.sections.borrow_mut();
-        let section = sections.last_mut().expect("");
-        // data
-        section.data.append(&mut data);
-        input
+            }
+        }
+        if mem_sizes.len() > 1{
+            return Err(ae.diagnostic(word, Code::SizeNotSpecified, "Operation size not specified.").note("Add byte, word, dword or qword to the memory operand."));
+        }
+        if let Some(value) = sign_extended{
+            let message = format!("Dword value {:#x} is sign extended to {:#x}.", value, value as i32 as i64);
+            self.warn("sign-extend", word, &message);
+        }
+        match (best, error){
+            (Some((data, reloc)), _) => self.emit(data, reloc),
+            (None, Some((code, mes))) => ae.error_from_word(word, code, mes),
+            (None, None) => ae.error_from_word(word, Code::InvalidOperands, "Invalid combination of operands."),
+        }
     }
-    
-    fn read_2args(&self, mut input: & 'a str) -> ((Value, & 'a str, Value, & 'a str), & 'a str){
-        let value1 :Value;
-        let value2 :Value;
-        //########################################################################
-        let value1str = input;
-        let (s, value) = self.read_value_unwrap(input, "mov: Expect Register or Memory");
+    // operand, operand, ... up to the end of the line
+    fn read_args(&self, mut input: & 'a str) -> Result<(Vec<Value<'a>>, & 'a str), Diagnostic>{
+        let mut args = Vec::new();
+        input = self.ignore_space(input);
+        if input.is_empty() || is_ignore_comment(input){
+            return Ok((args, input));
+        }
+        loop{
+            let (s, value) = self.read_value_unwrap(input, "Expect Register, Memory, Label or Figure.")?;
+            args.push(value);
+            input = self.ignore_space(s);
+            if !input.starts_with(','){
+                break;
+            }
+            input = self.read_comma(input)?;
+            input = self.ignore_space(input);
+        }
+        Ok((args, input))
+    }
+    // [rel|abs base + index*scale + label + disp]
+    fn read_mem(&self, mut input: & 'a str) -> Result<(& 'a str, Mem<'a>), Diagnostic>{
+        let ae = self.asm_error();
+        let first = input;
+        let Some(s) = input.strip_prefix('[') else{
+            return ae.error_from_word(input, Code::RequireBracket, "Require \'[\'.");
+        };
         input = s;
-        value1 = value;
-        //########################################################################
-        input = self.read_comma(input);
-        //########################################################################
+        let mut mem = Mem::new();
+        // explicit rel or abs
+        let mut rel = None;
         input = self.ignore_space(input);
-        let value2str = input;
-        let (s, value) = self.read_value_unwrap(input, "mov: Expect Register, Memory,or Memory");
+        if let Ok((s, word)) = get_word(input){
+            match word.to_lowercase().as_str(){
+                "rel" =>{rel = Some(true); input = s;},
+                "abs" =>{rel = Some(false); input = s;},
+                _ =>{},
+            }
+        }
+        input = self.ignore_space(input);
+        let terms = input;
+        let (s, mut linear) = self.read_linear(input)?;
+        input = self.ignore_space(s);
+        // registers with their scale
+        for (reg, size, scale) in linear.take_regs(){
+            if size != 8{
+                return ae.error_from_word(terms, Code::RequireRegister, "Expect 64bit Register.");
+            }
+            if scale < 0{
+                return ae.error_from_word(terms, Code::SubtractRegister, "Can't subtract a register.");
+            }
+            let scale = if scale == 1 {None} else {Some(scale.min(0xFF) as u8)};
+            if let Err((code, mes)) = mem.add_reg(reg, scale){
+                return ae.error_from_word(terms, code, mes);
+            }
+        }
+        match linear.to_expr(){
+            Ok(expr) =>{
+                mem.disp = expr.value;
+                mem.label = expr.label;
+            },
+            Err((code, mes)) => self.defer_error(terms, code, mes),
+        }
+        // label wrt ..gotpcrel
+        if let Some(s) = self.read_wrt(input, "..gotpcrel")?{
+            mem.got = true;
+            input = self.ignore_space(s);
+        }
+        let Some(s) = input.strip_prefix(']') else{
+            return ae.error_from_word(input, Code::RequireBracket, "Require \']\'.");
+        };
         input = s;
-        value2 = value;
-        //########################################################################
-
-        ((value1, value1str, value2, value2str), input)
+        let no_reg = mem.base.is_none() && mem.index.is_none();
+        mem.rel = rel.unwrap_or(no_reg && mem.label.is_some() && self.default_rel.get());
+        if mem.got && !(mem.rel && no_reg){
+            return ae.error_from_word(first, Code::GotNotRelative, "..gotpcrel must be rip relative.");
+        }
+        if let Err((code, mes)) = mem.fix(){
+            return ae.error_from_word(first, code, mes);
+        }
+        Ok((input, mem))
     }
-    fn read_value(&self, mut input: & 'a str) -> Result<(&'a str, Value), &'a str>{
+    // None if it is not an operand
+    fn read_value(&self, mut input: & 'a str) -> Result<Option<(&'a str, Value<'a>)>, Diagnostic>{
         let value;
-        if let Ok((s, t)) = get_word(input){
+        if input.starts_with('['){
+            let (s, mem) = self.read_mem(input)?;
             input = s;
-            let reg = r::reg(t);
-            if let Ok(reg) = reg{
-                value = reg;
-            }else{
-                return Err(input);
+            value = Value::Mem(mem);
+        }else if let Some((s, size)) = get_size(input){
+            // byte [mem] .. qword [mem]
+            input = s;
+            if !input.starts_with('['){
+                return Ok(None);
             }
-        }else if let Ok((s, t)) = get_figure(input){
+            let (s, mut mem) = self.read_mem(input)?;
+            input = s;
+            mem.size = size;
+            value = Value::Mem(mem);
+        }else if let Some((s, reg)) = get_word(input).ok().and_then(|(s, t)| Some((s, reg::reg(t).ok()?))){
             input = s;
-            value = Value::Figure(t);
+            value = reg;
+        }else if Asm::is_expr(input){
+            let (s, expr) = self.read_expr(input)?;
+            input = s;
+            value = Value::Imm(expr);
         }else {
-            return Err(input);
+            return Ok(None);
         }
-        Ok((input, value))
+        Ok(Some((input, value)))
     }
-    fn read_value_unwrap(&self, input: & 'a str, message: & str) -> (& 'a str, Value){
-        if let Ok((s, value)) = self.read_value(input){
-            return (s, value);
-        }else{
-            let ae = AsmError::new(self.m_contents);
-            ae.panic_from_word(input, message);
-            panic!();
+    fn read_value_unwrap(&self, input: & 'a str, message: & str) -> Result<(& 'a str, Value<'a>), Diagnostic>{
+        match self.read_value(input)?{
+            Some(value) => Ok(value),
+            None =>{
+                let ae = self.asm_error();
+                ae.error_from_word(input, Code::RequireOperand, message)
+            },
         }
     }
-    fn read_comma(&self, mut input: & 'a str)-> & 'a str{
+    fn read_comma(&self, mut input: & 'a str)-> Result<& 'a str, Diagnostic>{
         // ignore space
         input = self.ignore_space(input);
         // read comma
         let Ok((s, comma)) = get_others(input) else {
-            let ae = AsmError::new(self.m_contents);
-            ae.panic_from_word(input, "Require Comma.");
-            panic!();
+            let ae = self.asm_error();
+            return ae.error_from_word(input, Code::RequireComma, "Require Comma.");
         };
         input = s;
         if comma == ","{}
         else{
-            let ae = AsmError::new(self.m_contents);
-            ae.panic_from_word(input, "Require \',\' .");
+            let ae = self.asm_error();
+            return ae.error_from_word(input, Code::RequireComma, "Require \',\' .");
         }
-        input
+        Ok(input)
     }
     fn ignore_space(&self, input: & 'a str) -> &'a str{
         if let Ok((s, _)) = ignore_space(input){
@@ -733,18 +1270,66 @@ impl<'a> Asm<'a>{
     }
     
 }
+// value of an expression without labels, for %if and %rep
+// the errors have no place, the caller gives it
+pub fn evaluate(text: &str) -> Result<i64, Diagnostic>{
+    let asm = Asm{m_contents: text, ..Default::default()};
+    let input = asm.ignore_space(text);
+    if !Asm::is_expr(input){
+        return Err(Diagnostic::error(Code::RequireFigure, "Require Figure."));
+    }
+    let (s, expr) = asm.read_expr(input).map_err(|mut diagnostic| {diagnostic.labels.clear(); diagnostic})?;
+    if !asm.ignore_space(s).is_empty(){
+        return Err(Diagnostic::error(Code::Syntax, "Syntax Error."));
+    }
+    if let Some((_, code, mes)) = asm.deferred.borrow().first(){
+        return Err(Diagnostic::error(*code, mes));
+    }
+    if expr.label.is_some(){
+        return Err(Diagnostic::error(Code::NotConstant, "Expression is not constant."));
+    }
+    Ok(expr.value)
+}
+// multi-byte nops recommended by Intel, up to 9 bytes each
+fn nops(mut len: usize) -> Vec<u8>{
+    const NOPS: [&[u8]; 9] = [
+        &[0x90],
+        &[0x66, 0x90],
+        &[0x0F, 0x1F, 0x00],
+        &[0x0F, 0x1F, 0x40, 0x00],
+        &[0x0F, 0x1F, 0x44, 0x00, 0x00],
+        &[0x66, 0x0F, 0x1F, 0x44, 0x00, 0x00],
+        &[0x0F, 0x1F, 0x80, 0x00, 0x00, 0x00, 0x00],
+        &[0x0F, 0x1F, 0x84, 0x00, 0x00, 0x00, 0x00, 0x00],
+        &[0x66, 0x0F, 0x1F, 0x84, 0x00, 0x00, 0x00, 0x00, 0x00],
+    ];
+    let mut data = Vec::new();
+    while len > 0{
+        let n = len.min(NOPS.len());
+        data.extend(NOPS[n - 1]);
+        len -= n;
+    }
+    data
+}
 fn read_chars<'a>(input: & 'a str, cnt: usize) -> IResult<&str, &str>{
     take(cnt)(input)
 }
-fn get_figure<'a>(input: & 'a str)->IResult<&str, &str>{
-    let (input, figure) = digit1(input)?;
-    Ok((input, figure))
+// byte word dword qword
+fn get_size(input: & str) -> Option<(& str, u8)>{
+    let (s, word) = get_word(input).ok()?;
+    let size = match word.to_lowercase().as_str(){
+        "byte" => 1,
+        "word" => 2,
+        "dword" => 4,
+        "qword" => 8,
+        _ => return None,
+    };
+    let (s, _) = ignore_space(s).unwrap_or((s, ""));
+    Some((s, size))
 }
-fn get_string<'a>(input: & 'a str) -> IResult<&str, &str>{
-    let(input, first) = alt((tag("'"), tag("\"")))(input)?;
-    let (input, m_string) = take_until(first)(input)?;
-    let (input, _) = tag(first)(input)?;
-    Ok((input, m_string))
+// , ; or the end of the line after a dx item
+fn is_item_end(input: &str) -> bool{
+    input.is_empty() || input.starts_with(',') || input.starts_with(';')
 }
 fn get_others<'a>(input: & 'a str) -> IResult<&str, &str>{
     let first = input;
@@ -758,6 +1343,14 @@ fn get_others<'a>(input: & 'a str) -> IResult<&str, &str>{
     Err(nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::AlphaNumeric)));
     return error;
 }
+// section names may contain $ and other symbols
+fn get_section_name(input: & str) -> IResult<&str, &str>{
+    let len = input.find(|c: char| c.is_whitespace() || c == ';' || c == ',').unwrap_or(input.len());
+    if len == 0{
+        return Err(nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::AlphaNumeric)));
+    }
+    Ok((&input[len..], &input[..len]))
+}
 fn get_word<'a >(input: & 'a str)->IResult<&str, & str> {
     let first = input;
     let mut input = input;
@@ -788,6 +1381,9 @@ fn get_word_backward<'a>( input: & 'a str)->IResult<& 'a str, & 'a str>{
         let (input, _) = many0_count(tag("_"))(input)?;
         // このあと計算イラン get_wordでする get_word で計算している
         Ok((input, "_"))
+    }else if ch == b'@'{
+        let (input, _) = many0_count(tag("@"))(input)?;
+        Ok((input, "@"))
     }else if ch == b'.'{
         let (input, _) = many0_count(tag("."))(input)?;
         // このあと計算イラン get_wordでする get_word で計算している
@@ -812,6 +1408,36 @@ fn ignore_comment<'a>(input: & 'a str) -> IResult<&str, &str>{
     let (input, _) = tag("\n")(input)?;
     Ok((input, ""))
 }
+fn dx_to_size(c: u8) -> Option<u8>{
+    match c{
+        b'b'=>Some(1),
+        b'w'=>Some(2),
+        b'd'=>Some(4),
+        b'q'=>Some(8),
+        b't'=>Some(10),
+        b'o'=>Some(16),
+        b'y'=>Some(32),
+        b'z'=>Some(64),
+        _ => None,
+    }
+}
+// db .. dz and resb .. resz, lowercase. (reserve, size)
+fn data_directive(word: &str) -> Option<(bool, u8)>{
+    let (reserve, c) = match word.len(){
+        2 if word.starts_with('d') => (false, word.as_bytes()[1]),
+        4 if word.starts_with("res") => (true, word.as_bytes()[3]),
+        _ => return None,
+    };
+    Some((reserve, dx_to_size(c)?))
+}
+// a word _instruction takes
+fn is_mnemonic(word: &str) -> bool{
+    let lower = word.to_lowercase();
+    let lower = lower.as_str();
+    DIRECTIVES.contains(&lower) || BRANCHES.contains(&lower) || PREFIXES.contains(&lower) || UNSUPPORTED.contains(&lower)
+        || insn::exists(lower) || data_directive(lower).is_some()
+        || lower.strip_prefix('j').is_some_and(|cc| condition(cc).is_some())
+}
 fn is_ignore_comment<'a>(input: & 'a str) -> bool{
     if get_str_first(input) == b';' {true} else {false}
 }
@@ -824,48 +1450,43 @@ fn ignore_space_and_return<'a>(input: & 'a str) -> IResult<& 'a str, & 'a str>{
 }
 pub struct AsmError<'a >{
     m_str: & 'a str,
+    m_map: Option<& 'a LineMap>,
 }
 
 impl<'a> AsmError<'a>{
-    pub fn new(_str: &'a str) -> Self{
-        Self{m_str: _str}
-    }
-    pub fn panic_from_pos(&self, first : usize, message: &str){
-        let mut lines = self.m_str.lines();
-        let mut loopcnt: usize = 0;
-        let mut linecnt = 0;
-        let mut rawcnt = 0;
-        let mut error_line : &str = "";
-        while let Some(line) = lines.next(){
-            loopcnt += 1;
+    pub fn new(_str: &'a str, map: Option<& 'a LineMap>) -> Self{
+        Self{m_str: _str, m_map: map}
+    }
+    // the line of a byte position in m_str, before preprocessing
+    pub fn span(&self, first: usize) -> Option<Span>{
+        for (i, line) in self.m_str.lines().enumerate(){
             let linefirst = wrapper_pos(self.m_str, line) as usize;
-
-            let lineend = linefirst + line.len();
-            if linefirst <= first && first < lineend {
-                linecnt = loopcnt;
+            // the end of the line for errors after the last word
+            if linefirst <= first && first <= linefirst + line.len(){
+                let word = &line[first - linefirst..];
+                let len = word.find(|c: char| !c.is_ascii_alphanumeric() && !"_.@$?#~".contains(c)).unwrap_or(word.len());
+                let (file, line_number) = match self.m_map.and_then(|map| map.origin(i)){
+                    Some((file, line_number)) => (file.to_string(), line_number),
+                    None => (String::new(), i + 1),
+                };
                 // 一応一行目芋締めから始まるため
-                rawcnt = first - linefirst + 1;
-                error_line = line;
-                break;
+                let column = first - linefirst + 1;
+                return Some(Span{file, line: line_number, column, len, text: line.to_string()});
             }
         }
-        let mut spaces = String::from("");
-        for _ in 1..rawcnt{
-            spaces += " ";
-        }
-        println!("######ERROR");
-        println!("{}, {}", linecnt, rawcnt);
-        println!("{}", error_line);
-        println!("{}^", spaces);
-        println!("{}", message);
-        panic!();
+        None
+    }
+    // words outside m_str have no span
+    pub fn span_of(&self, word: &str) -> Option<Span>{
+        let first = (word.as_ptr() as usize).checked_sub(self.m_str.as_ptr() as usize)?;
+        self.span(first)
     }
-    pub fn panic_from_word(&self, word: & 'a str, message: & str){
-        self.panic_from_pos(wrapper_pos(self.m_str, word) as usize, message)
+    pub fn diagnostic(&self, word: &str, code: Code, message: &str) -> Diagnostic{
+        Diagnostic::error(code, message).label(self.span_of(word), "")
     }
-    pub fn panic_from_word_idx(&self, word: & 'a str, idx: isize, message: & str){
-        self.panic_from_pos((wrapper_pos(self.m_str, word) + idx) as usize,
-    message);
+    // Err with the word as the primary label
+    pub fn error_from_word<T>(&self, word: &str, code: Code, message: &str) -> Result<T, Diagnostic>{
+        Err(self.diagnostic(word, code, message))
     }
 }
 fn wrapper_pos(origin: &str, branch: &str) -> isize{
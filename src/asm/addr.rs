@@ -0,0 +1,128 @@
+use super::reg::{self as r, create_modrm};
+use crate::diagnostic::{Code, Error};
+// effective address [base + index*scale + label + disp]
+#[derive(Default, Clone, Copy)]
+pub struct Mem<'a>{
+    pub base: Option<u8>,
+    pub index: Option<u8>,
+    pub scale: u8,
+    pub disp: i64,
+    pub label: Option<& 'a str>,
+    // rip relative
+    pub rel: bool,
+    // got entry of the label (wrt ..gotpcrel)
+    pub got: bool,
+    // byte .. qword, 0 if not specified
+    pub size: u8,
+}
+impl<'a> Mem<'a>{
+    pub fn new() -> Self{
+        Self{scale: 1, ..Default::default()}
+    }
+    // [reg] or [reg * scale]
+    pub fn add_reg(&mut self, reg: u8, scale: Option<u8>) -> Result<(), Error>{
+        match scale{
+            None =>{
+                if self.base.is_none(){
+                    self.base = Some(reg);
+                }else if self.index.is_none(){
+                    self.index = Some(reg);
+                    self.scale = 1;
+                }else{
+                    return Err((Code::TooManyRegisters, "Too many registers."));
+                }
+            },
+            Some(scale) =>{
+                if self.index.is_some(){
+                    return Err((Code::TooManyRegisters, "Too many index registers."));
+                }
+                match scale{
+                    1 | 2 | 4 | 8 =>{
+                        self.index = Some(reg);
+                        self.scale = scale;
+                    },
+                    // [rax*3] -> [rax+rax*2]
+                    3 | 5 | 9 if self.base.is_none() =>{
+                        self.base = Some(reg);
+                        self.index = Some(reg);
+                        self.scale = scale - 1;
+                    },
+                    _ => return Err((Code::InvalidScale, "Scale must be 1, 2, 4 or 8.")),
+                }
+            },
+        }
+        Ok(())
+    }
+    // rsp can't be an index
+    pub fn fix(&mut self) -> Result<(), Error>{
+        if self.rel && (self.base.is_some() || self.index.is_some()){
+            return Err((Code::RelWithRegisters, "rel can't be used with registers."));
+        }
+        if self.index == Some(r::RSP){
+            if self.scale == 1 && self.base.is_some() && self.base != Some(r::RSP){
+                std::mem::swap(&mut self.base, &mut self.index);
+            }else{
+                return Err((Code::RspIndex, "rsp can't be used as an index register."));
+            }
+        }
+        if self.disp < -0x8000_0000 || self.disp > 0xffff_ffff{
+            return Err((Code::LargeDisplacement, "Displacement must be 32bit."));
+        }
+        Ok(())
+    }
+    pub fn rex_x(&self) -> u8{
+        self.index.map_or(0, |i| (i & 0b1000) >> 3)
+    }
+    pub fn rex_b(&self) -> u8{
+        self.base.map_or(0, |b| (b & 0b1000) >> 3)
+    }
+    // modr/m + sib + disp
+    pub fn encode(&self, reg: u8) -> Vec<u8>{
+        let mut data = Vec::<u8>::new();
+        let reg = reg & 0b111;
+        let disp = self.disp as i32;
+        // labels are relocated as disp32
+        let disp8 = (-0x80..0x80).contains(&self.disp) && self.label.is_none();
+        if self.rel{
+            // [rip + disp32]
+            data.push(create_modrm(0b00, reg, 0b101));
+            data.extend(disp.to_le_bytes());
+            return data;
+        }
+        let Some(base) = self.base else{
+            // no base: SIB with base=101 and disp32
+            let index = self.index.map_or(0b100, |i| i & 0b111);
+            data.push(create_modrm(0b00, reg, 0b100));
+            data.push(create_sib(self.scale, index, 0b101));
+            data.extend(disp.to_le_bytes());
+            return data;
+        };
+        let base = base & 0b111;
+        // [rbp] and [r13] need disp8
+        let modf = if self.disp == 0 && self.label.is_none() && base != r::RBP {0b00}
+                   else if disp8 {0b01}
+                   else {0b10};
+        if self.index.is_some() || base == r::RSP{
+            let index = self.index.map_or(0b100, |i| i & 0b111);
+            data.push(create_modrm(modf, reg, 0b100));
+            data.push(create_sib(self.scale, index, base));
+        }else{
+            data.push(create_modrm(modf, reg, base));
+        }
+        match modf{
+            0b01 => data.push(disp as u8),
+            0b10 => data.extend(disp.to_le_bytes()),
+            _ => {},
+        }
+        data
+    }
+}
+pub fn create_sib(scale: u8, index: u8, base: u8) -> u8{
+    let ss = match scale{
+        2 => 0b01,
+        4 => 0b10,
+        8 => 0b11,
+        _ => 0b00,
+    };
+    create_modrm(ss, index, base)
+}
@@ -0,0 +1,119 @@
+use std::io::Write;
+use super::reloc::RelocKind;
+use super::object::{Module, ObjectFormat, Target};
+use super::SectionKind;
+use crate::diagnostic::{Code, Diagnostic};
+// default alignment of sections without align=
+const SECTION_ALIGN: u64 = 4;
+// flat binary, every label resolved to its address
+pub struct Bin;
+impl ObjectFormat for Bin{
+    // no extension, like nasm
+    fn extension(&self) -> & 'static str{
+        ""
+    }
+    // progbits
+    fn other_section(&self) -> (SectionKind, u64){
+        (SectionKind::Rdata, SECTION_ALIGN)
+    }
+    fn write(&self, module: &Module, out: &mut dyn Write) -> Result<(), Diagnostic>{
+        let sections = module.sections;
+        // .text first, .bss last
+        let is_nobits = |i: &usize| sections[*i].is_nobits();
+        let mut order: Vec<usize> = (0..sections.len()).filter(|i| sections[*i].name == ".text").collect();
+        order.extend((0..sections.len()).filter(|i| sections[*i].name != ".text" && !is_nobits(i)));
+        order.extend((0..sections.len()).filter(is_nobits));
+        // follows= moves a section after another one
+        let next = |i: usize| sections[i].follows.and_then(|f| sections.iter().position(|s| s.name == f));
+        for i in 0..sections.len(){
+            let Some(follows) = sections[i].follows else{
+                continue;
+            };
+            let Some(target) = next(i) else{
+                return Err(module.error(follows, Code::UnknownSection, "Unknown section."));
+            };
+            // the chain of follows= must not come back
+            let mut j = Some(target);
+            for _ in 0..sections.len(){
+                match j{
+                    Some(k) if k == i =>{
+                        let message = format!("Section {} follows itself.", sections[i].name);
+                        return Err(module.error(follows, Code::FollowsCycle, &message));
+                    },
+                    Some(k) => j = next(k),
+                    None => break,
+                }
+            }
+            order.retain(|j| *j != i);
+            let pos = order.iter().position(|j| *j == target).unwrap();
+            order.insert(pos + 1, i);
+        }
+        // vstart of each section
+        let org = module.org;
+        let mut vstart = vec![0u64; sections.len()];
+        let mut cursor = org;
+        for &i in &order{
+            let sec = &sections[i];
+            let align = sec.align.unwrap_or(SECTION_ALIGN);
+            let start = sec.start.unwrap_or(cursor.div_ceil(align) * align);
+            if start < org{
+                return Err(module.error(sec.name, Code::SectionBeforeOrg, &format!("Section {} starts before org.", sec.name)));
+            }
+            vstart[i] = start;
+            cursor = start + sec.size() as u64;
+        }
+        let address = |name: &str| -> Result<u64, Diagnostic>{
+            let idx = match module.target(name).unwrap(){
+                Target::Section(i) => return Ok(vstart[i]),
+                Target::Symbol(i) => i,
+            };
+            let symbol = &module.symbols[idx];
+            if symbol.section == 0{
+                return Err(module.error(symbol.name, Code::BinExtern, "Can't use extern or common in bin."));
+            }
+            Ok(vstart[symbol.section - 1] + symbol.value)
+        };
+        // image from org to the end of the last initialized section
+        let mut image = Vec::<u8>::new();
+        let mut filled = Vec::<bool>::new();
+        for &i in order.iter().filter(|i| !is_nobits(i)){
+            let sec = &sections[i];
+            let mut data = sec.data.clone();
+            for reloc in &sec.relocations{
+                let target = address(reloc.symbol)? as i64 + reloc.addend(&sec.data);
+                let value = match reloc.kind{
+                    RelocKind::Addr64 | RelocKind::Addr32 | RelocKind::Addr32S | RelocKind::Addr16 => target,
+                    RelocKind::Rel32(_) | RelocKind::Plt32 =>{
+                        let rip = (vstart[i] + reloc.offset as u64) as i64 + reloc.kind.pc_bias();
+                        target - rip
+                    },
+                    _ => return Err(module.error(reloc.symbol, Code::UnsupportedRelocation, "Can't use this relocation in bin.")),
+                };
+                let fits = match reloc.kind{
+                    RelocKind::Addr64 => true,
+                    RelocKind::Addr32 => (0..1 << 32).contains(&value),
+                    RelocKind::Addr16 => (0..1 << 16).contains(&value),
+                    _ => (-0x8000_0000..0x8000_0000).contains(&value),
+                };
+                if !fits{
+                    return Err(module.error(reloc.symbol, Code::AddressOverflow, "Address doesn't fit the field."));
+                }
+                let size = reloc.kind.size();
+                data[reloc.offset..reloc.offset + size].copy_from_slice(&value.to_le_bytes()[..size]);
+            }
+            let start = (vstart[i] - org) as usize;
+            let end = start + data.len();
+            if image.len() < end{
+                image.resize(end, 0);
+                filled.resize(end, false);
+            }
+            if filled[start..end].iter().any(|f| *f){
+                return Err(module.error(sec.name, Code::SectionOverlap, &format!("Section {} overlaps another section.", sec.name)));
+            }
+            image[start..end].copy_from_slice(&data);
+            filled[start..end].fill(true);
+        }
+        out.write_all(&image)?;
+        Ok(())
+    }
+}
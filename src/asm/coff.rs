@@ -0,0 +1,182 @@
+use std::io::Write;
+use std::mem;
+use super::headers::*;
+use super::object::{Bind, Module, ObjectFormat, Target};
+use super::{Section, SectionKind};
+use crate::diagnostic::{Code, Diagnostic};
+// PE/COFF object for x86-64
+pub struct Coff;
+// names longer than 8 bytes
+struct StringTable{
+    data: Vec<u8>,
+    // a section name is also the name of its symbol
+    offsets: Vec<(String, usize)>,
+}
+impl StringTable{
+    fn new() -> Self{
+        // the size comes first
+        Self{data: vec![0; 4], offsets: Vec::new()}
+    }
+    fn add(&mut self, name: &str) -> usize{
+        if let Some((_, offset)) = self.offsets.iter().find(|(n, _)| n == name){
+            return *offset;
+        }
+        let offset = self.data.len();
+        self.data.extend(name.as_bytes());
+        self.data.push(0);
+        self.offsets.push((name.to_string(), offset));
+        offset
+    }
+    // zeroes and offset
+    fn symbol_name(&mut self, name: &str) -> [u8; 8]{
+        let mut ret = [0u8; 8];
+        if name.len() <= 8{
+            ret[..name.len()].copy_from_slice(name.as_bytes());
+        }else{
+            ret[4..].copy_from_slice(&(self.add(name) as u32).to_le_bytes());
+        }
+        ret
+    }
+    // /offset
+    fn section_name(&mut self, name: &str) -> [u8; 8]{
+        if name.len() <= 8{
+            return self.symbol_name(name);
+        }
+        let offset = format!("/{}", self.add(name));
+        let mut ret = [0u8; 8];
+        ret[..offset.len()].copy_from_slice(offset.as_bytes());
+        ret
+    }
+    fn finish(mut self) -> Vec<u8>{
+        let size = self.data.len() as u32;
+        self.data[..4].copy_from_slice(&size.to_le_bytes());
+        self.data
+    }
+}
+fn section_header(module: &Module, sec: &Section, strings: &mut StringTable) -> Result<SECTION_HEADER, Diagnostic>{
+    let mut sh = SECTION_HEADER{
+        Name: strings.section_name(sec.name),
+        SizeOfRawData: sec.size() as u32,
+        NumberOfRelocations: sec.relocations.len() as u16,
+        ..Default::default()
+    };
+    sh.Characteristics = characteristics(module, sec)?;
+    Ok(sh)
+}
+fn characteristics(module: &Module, sec: &Section) -> Result<u32, Diagnostic>{
+    let mut flags = match sec.kind{
+        SectionKind::Code => IMAGE_SCN_CNT_CODE | IMAGE_SCN_MEM_READ,
+        SectionKind::Data | SectionKind::Rdata => IMAGE_SCN_CNT_INITIALIZED_DATA | IMAGE_SCN_MEM_READ,
+        SectionKind::Bss => IMAGE_SCN_CNT_UNINITIALIZED_DATA | IMAGE_SCN_MEM_READ,
+        SectionKind::Info => IMAGE_SCN_LNK_INFO | IMAGE_SCN_LNK_REMOVE,
+    };
+    if sec.is_exec(){
+        flags |= IMAGE_SCN_MEM_EXECUTE;
+    }
+    if sec.is_write(){
+        flags |= IMAGE_SCN_MEM_WRITE;
+    }
+    // ALIGN_1BYTES .. ALIGN_8192BYTES
+    let align = sec.alignment();
+    if align > 8192{
+        return Err(module.error(sec.name, Code::SectionAlignment, &format!("Section {} can't be aligned to {} in win64.", sec.name, align)));
+    }
+    Ok(flags | (IMAGE_SCN_ALIGN_1BYTES * (align.trailing_zeros() + 1)))
+}
+impl ObjectFormat for Coff{
+    fn extension(&self) -> & 'static str{
+        "obj"
+    }
+    // like .text
+    fn other_section(&self) -> (SectionKind, u64){
+        (SectionKind::Code, 16)
+    }
+    fn write(&self, module: &Module, out: &mut dyn Write) -> Result<(), Diagnostic>{
+        let sections = module.sections;
+        let mut p_data = mem::size_of::<FILE_HEADER>() + sections.len() * mem::size_of::<SECTION_HEADER>();
+        // FILE_HEADER
+        let mut file_header = FILE_HEADER::new();
+        file_header.Machine = 0x8664;
+        file_header.NumberOfSections = sections.len() as u16;
+        file_header.TimeDataStamp = chrono::Local::now().timestamp() as u32;
+        let mut strings = StringTable::new();
+        // SECTION
+        let mut section_headers = Vec::<SECTION_HEADER>::new();
+        for sec in sections{
+            let mut section_header = section_header(module, sec, &mut strings)?;
+            // nobits has no raw data
+            if !sec.is_nobits(){
+                section_header.PointerToRawData = p_data as u32;
+                p_data += sec.data.len();
+            }
+            section_header.PointerToRelocations = p_data as u32;
+            p_data += sec.relocations.len() * mem::size_of::<RELOCATION>();
+            section_headers.push(section_header);
+        }
+        // symbols: .file, sections and labels
+        let mut symbol_table = Vec::<u8>::new();
+        // the file name fills 18 bytes aux records
+        let mut file = SYMBOL_TABLE::new_dot_file();
+        file.NumberOfAuxSymbols = module.file.len().div_ceil(0x12).max(1) as u8;
+        symbol_table.extend(as_u8_slice(&file));
+        let mut aux = vec![0u8; file.NumberOfAuxSymbols as usize * 0x12];
+        aux[..module.file.len()].copy_from_slice(module.file.as_bytes());
+        symbol_table.extend(aux);
+        for (i, sec) in sections.iter().enumerate(){
+            let symbol = SYMBOL_TABLE{
+                Name: strings.symbol_name(sec.name),
+                SectionNumber: 1u16 + i as u16,
+                StorageClass: 3,
+                NumberOfAuxSymbols: 1,
+                ..Default::default()
+            };
+            let mut symbol_define_section = SYMBOL_U8::default();
+            symbol_define_section.set(&(sec.size() as u32), 0);
+            symbol_define_section.set(&(sec.relocations.len() as u16), 4);
+            symbol_table.extend(as_u8_slice(&symbol));
+            symbol_table.extend(symbol_define_section.data);
+        }
+        for sym in &module.symbols{
+            let symbol = SYMBOL_TABLE{
+                Name: strings.symbol_name(sym.name),
+                Value: sym.value as u32,
+                SectionNumber: sym.section as u16,
+                // static or external
+                StorageClass: if sym.bind == Bind::Local {3} else {2},
+                ..Default::default()
+            };
+            symbol_table.extend(as_u8_slice(&symbol));
+        }
+        file_header.NumberOfSymbols = (symbol_table.len() / 0x12) as u32;
+        file_header.PointerToSymbolTable = p_data as u32;
+        symbol_table.extend(strings.finish());
+        // * Writing
+        out.write_all(as_u8_slice(&file_header))?;
+        for sh in &section_headers{
+            out.write_all(as_u8_slice(sh))?;
+        }
+        // data and relocations
+        for sec in sections{
+            out.write_all(&sec.data)?;
+            for reloc in &sec.relocations{
+                let Some(kind) = reloc.kind.coff_type() else{
+                    return Err(module.error(reloc.symbol, Code::UnsupportedRelocation, "Can't use this relocation in win64."));
+                };
+                // .file + aux + sections(2 each) + symbols
+                let first = 1 + file.NumberOfAuxSymbols as usize;
+                let idx = match module.target(reloc.symbol).unwrap(){
+                    Target::Section(i) => first + i * 2,
+                    Target::Symbol(i) => first + sections.len() * 2 + i,
+                };
+                let relocation = RELOCATION{
+                    VirtualAddress: reloc.offset as u32,
+                    SymbolTableIndex: idx as u32,
+                    Type: kind,
+                };
+                out.write_all(as_u8_slice(&relocation))?;
+            }
+        }
+        out.write_all(&symbol_table)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,45 @@
+use super::{Asm, get_word};
+use super::expr::Linear;
+use crate::diagnostic::{Code, Diagnostic};
+impl<'a> Asm<'a>{
+    // the rest of the line after equ
+    pub fn read_equ(&self, input: & 'a str) -> Option<& 'a str>{
+        let (s, word) = get_word(self.ignore_space(input)).ok()?;
+        word.eq_ignore_ascii_case("equ").then_some(s)
+    }
+    // name equ expr
+    // kept across passes like labels, so it can be used before the definition
+    pub fn equ(&self, name: & 'a str, input: & 'a str) -> Result<& 'a str, Diagnostic>{
+        let ae = self.asm_error();
+        let input = self.ignore_space(input);
+        if !Asm::is_expr(input){
+            return ae.error_from_word(input, Code::RequireFigure, "Require Figure.");
+        }
+        let (s, value) = self.read_expr(input)?;
+        if let Some(label) = self.labels.borrow().iter().find(|l| l.name == name){
+            return Err(ae.diagnostic(name, Code::LabelRedefined, "Label redefined.").label(ae.span_of(label.name), "defined here as a label"));
+        }
+        let pass = self.pass.get();
+        let mut equs = self.equs.borrow_mut();
+        if let Some(equ) = equs.iter_mut().find(|(n, _, _)| *n == name){
+            if equ.2 == pass{
+                return Err(ae.diagnostic(name, Code::SymbolRedefined, "Symbol redefined.").label(ae.span_of(equ.0), "first defined here"));
+            }
+            if equ.1 != value{
+                self.changed.set(true);
+            }
+            equ.1 = value;
+            equ.2 = pass;
+        }else{
+            equs.push((name, value, pass));
+            self.changed.set(true);
+        }
+        Ok(s)
+    }
+    // value of an equ
+    pub fn constant(&self, name: & 'a str) -> Option<Linear<'a>>{
+        let equs = self.equs.borrow();
+        let (_, value, _) = equs.iter().find(|(n, _, _)| *n == name)?;
+        Some(self.expr_linear(*value))
+    }
+}
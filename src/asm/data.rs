@@ -0,0 +1,439 @@
+use std::fs;
+use std::num::IntErrorKind;
+use std::path::Path;
+use super::{Asm, get_word};
+use crate::diagnostic::{Code, Diagnostic, Error};
+// 'text', "text" or `text with \escapes`
+pub fn get_quoted(input: &str) -> Result<(&str, Vec<u8>), Error>{
+    let quote = input.chars().next().filter(|c| "'\"`".contains(*c)).ok_or((Code::RequireData, "Require \', \" or `."))?;
+    let body = &input[1..];
+    if quote != '`'{
+        let len = body.find(quote).ok_or((Code::UnterminatedString, "Unterminated string."))?;
+        return Ok((&body[len + 1..], body.as_bytes()[..len].to_vec()));
+    }
+    let mut bytes = Vec::new();
+    let mut chars = body.char_indices().peekable();
+    while let Some((i, c)) = chars.next(){
+        match c{
+            '`' => return Ok((&body[i + 1..], bytes)),
+            '\\' =>{
+                let Some((_, e)) = chars.next() else{
+                    break;
+                };
+                match e{
+                    'a' => bytes.push(7),
+                    'b' => bytes.push(8),
+                    't' => bytes.push(9),
+                    'n' => bytes.push(10),
+                    'v' => bytes.push(11),
+                    'f' => bytes.push(12),
+                    'r' => bytes.push(13),
+                    'e' => bytes.push(27),
+                    // \0 .. \777
+                    '0'..='7' =>{
+                        let mut value = e.to_digit(8).unwrap();
+                        for _ in 0..2{
+                            let Some(d) = chars.peek().and_then(|(_, c)| c.to_digit(8)) else{
+                                break;
+                            };
+                            value = value * 8 + d;
+                            chars.next();
+                        }
+                        bytes.push(value as u8);
+                    },
+                    // \xHH, \uHHHH, \UHHHHHHHH
+                    'x' | 'u' | 'U' =>{
+                        let max = match e {'x' => 2, 'u' => 4, _ => 8};
+                        let mut value = 0u32;
+                        let mut n = 0;
+                        while n < max{
+                            let Some(d) = chars.peek().and_then(|(_, c)| c.to_digit(16)) else{
+                                break;
+                            };
+                            value = value * 16 + d;
+                            chars.next();
+                            n += 1;
+                        }
+                        if n == 0{
+                            return Err((Code::InvalidEscape, "Invalid escape."));
+                        }
+                        if e == 'x'{
+                            bytes.push(value as u8);
+                        }else{
+                            let c = char::from_u32(value).ok_or((Code::InvalidCharacter, "Invalid character."))?;
+                            bytes.extend(c.to_string().as_bytes());
+                        }
+                    },
+                    // \' \" \` \\ \?
+                    _ => bytes.extend(e.to_string().as_bytes()),
+                }
+            },
+            _ => bytes.extend(c.to_string().as_bytes()),
+        }
+    }
+    Err((Code::UnterminatedString, "Unterminated string."))
+}
+// __utf16__("text") and friends
+pub fn utf(name: &str, bytes: &[u8]) -> Result<Vec<u8>, Error>{
+    let text = std::str::from_utf8(bytes).map_err(|_| (Code::InvalidUtf8, "Invalid UTF-8."))?;
+    let ret = match name{
+        "__utf16__" | "__utf16le__" => text.encode_utf16().flat_map(u16::to_le_bytes).collect(),
+        "__utf16be__" => text.encode_utf16().flat_map(u16::to_be_bytes).collect(),
+        "__utf32__" | "__utf32le__" => text.chars().flat_map(|c| (c as u32).to_le_bytes()).collect(),
+        "__utf32be__" => text.chars().flat_map(|c| (c as u32).to_be_bytes()).collect(),
+        _ => return Err((Code::StringFunction, "Unknown string function.")),
+    };
+    Ok(ret)
+}
+pub enum Float{
+    // digits and the power of ten
+    Decimal(String, i64),
+    Infinity,
+    QNaN,
+    SNaN,
+}
+// [-+] 1.5, 1.5e-3, 1e10, __Infinity__, __QNaN__, __SNaN__
+pub fn get_float(input: &str) -> Result<Option<(&str, bool, Float)>, Error>{
+    let Some(first) = input.chars().next() else{
+        return Ok(None);
+    };
+    let (negative, s) = match first{
+        '-' => (true, input[1..].trim_start()),
+        '+' => (false, input[1..].trim_start()),
+        _ => (false, input),
+    };
+    if let Some((rest, word)) = get_word(s).ok().filter(|_| s.starts_with('_')){
+        let special = match word{
+            "__Infinity__" => Float::Infinity,
+            "__QNaN__" | "__NaN__" => Float::QNaN,
+            "__SNaN__" => Float::SNaN,
+            _ => return Ok(None),
+        };
+        return Ok(Some((rest, negative, special)));
+    }
+    let len = s.find(|c: char| !c.is_ascii_digit() && c != '_').unwrap_or(s.len());
+    if len == 0{
+        return Ok(None);
+    }
+    let too_large = (Code::LargeExponent, "Exponent is too large.");
+    let mut digits = s[..len].replace('_', "");
+    let mut exp = 0i64;
+    let mut rest = &s[len..];
+    let mut float = false;
+    if let Some(r) = rest.strip_prefix('.'){
+        let len = r.find(|c: char| !c.is_ascii_digit() && c != '_').unwrap_or(r.len());
+        let fraction = r[..len].replace('_', "");
+        exp = exp.checked_sub(fraction.len() as i64).ok_or(too_large)?;
+        digits += &fraction;
+        rest = &r[len..];
+        float = true;
+    }
+    if let Some(r) = rest.strip_prefix(['e', 'E']){
+        let len = r.find(|c: char| !c.is_ascii_digit() && c != '-' && c != '+').unwrap_or(r.len());
+        let e = match r[..len].parse::<i64>(){
+            Ok(e) => e,
+            Err(e) if matches!(e.kind(), IntErrorKind::PosOverflow | IntErrorKind::NegOverflow) => return Err(too_large),
+            Err(_) => return Ok(None),
+        };
+        exp = exp.checked_add(e).ok_or(too_large)?;
+        rest = &r[len..];
+        float = true;
+    }
+    // 1eh is a figure
+    if !float || rest.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_'){
+        return Ok(None);
+    }
+    Ok(Some((rest, negative, Float::Decimal(digits, exp))))
+}
+// IEEE-754 single, double or x87 extended
+pub fn float_bytes(negative: bool, float: &Float, size: u8) -> Result<Vec<u8>, Error>{
+    match size{
+        4 =>{
+            let bits = match float{
+                Float::Decimal(digits, exp) => format!("{}e{}", digits, exp).parse::<f32>().unwrap().to_bits(),
+                Float::Infinity => 0x7F80_0000,
+                Float::QNaN => 0x7FC0_0000,
+                Float::SNaN => 0x7F80_0001,
+            };
+            Ok((bits | (negative as u32) << 31).to_le_bytes().to_vec())
+        },
+        8 =>{
+            let bits = match float{
+                Float::Decimal(digits, exp) => format!("{}e{}", digits, exp).parse::<f64>().unwrap().to_bits(),
+                Float::Infinity => 0x7FF0_0000_0000_0000,
+                Float::QNaN => 0x7FF8_0000_0000_0000,
+                Float::SNaN => 0x7FF0_0000_0000_0001,
+            };
+            Ok((bits | (negative as u64) << 63).to_le_bytes().to_vec())
+        },
+        10 =>{
+            let (exponent, mantissa) = match float{
+                Float::Decimal(digits, exp) => extended(digits, *exp),
+                Float::Infinity => (0x7FFF, 1 << 63),
+                Float::QNaN => (0x7FFF, 3 << 62),
+                Float::SNaN => (0x7FFF, 1 << 63 | 1),
+            };
+            let mut bytes = mantissa.to_le_bytes().to_vec();
+            bytes.extend((exponent | (negative as u16) << 15).to_le_bytes());
+            Ok(bytes)
+        },
+        _ => Err((Code::FloatSize, "Floats need dd, dq or dt.")),
+    }
+}
+// biased exponent and mantissa with the explicit integer bit, rounded to nearest even.
+// too small values become denormals or 0
+fn extended(digits: &str, exp: i64) -> (u16, u64){
+    let digits = digits.trim_start_matches('0');
+    if digits.is_empty(){
+        return (0, 0);
+    }
+    // 1.19e4932 is the largest and 3.65e-4951 the smallest denormal
+    let magnitude = exp.saturating_add(digits.len() as i64);
+    if magnitude > 4933{
+        return (0x7FFF, 1 << 63);
+    }
+    if magnitude < -4950{
+        return (0, 0);
+    }
+    let mut m = Big::default();
+    for d in digits.bytes(){
+        m.mul_add(10, (d - b'0') as u32);
+    }
+    let mut ten = Big::default();
+    ten.mul_add(1, 1);
+    for _ in 0..exp.unsigned_abs(){
+        ten.mul_add(10, 0);
+    }
+    // q * 2^-shift with the remainder as sticky
+    let (q, shift, inexact) = if exp >= 0{
+        m.mul(&ten);
+        (m, 0, false)
+    }else{
+        let shift = (66 + ten.bits()).saturating_sub(m.bits());
+        let (q, r) = m.shl(shift).divmod(&ten);
+        (q, shift, r.bits() != 0)
+    };
+    let len = q.bits();
+    let mut exponent = len as i64 - 1 - shift as i64 + 16383;
+    // denormals keep fewer bits
+    let keep = if exponent > 0 { 64 } else { 63 + exponent };
+    if keep < 0{
+        return (0, 0);
+    }
+    let keep = keep as usize;
+    let mut mantissa = 0u64;
+    for i in 0..keep{
+        mantissa = mantissa << 1 | (len > i && q.bit(len - 1 - i)) as u64;
+    }
+    let round = len > keep && q.bit(len - 1 - keep);
+    let sticky = inexact || (0..len.saturating_sub(keep + 1)).any(|i| q.bit(i));
+    if round && (sticky || mantissa & 1 != 0){
+        mantissa = mantissa.wrapping_add(1);
+        if mantissa == 0{
+            mantissa = 1 << 63;
+            exponent += 1;
+        }
+    }
+    if exponent >= 0x7FFF{
+        return (0x7FFF, 1 << 63);
+    }
+    if mantissa >> 63 == 0{
+        return (0, mantissa);
+    }
+    (exponent.max(1) as u16, mantissa)
+}
+// unsigned integer, little endian 32 bit limbs
+#[derive(Clone, Default)]
+struct Big(Vec<u32>);
+impl Big{
+    fn mul_add(&mut self, m: u32, a: u32){
+        let mut carry = a as u64;
+        for limb in self.0.iter_mut(){
+            let v = *limb as u64 * m as u64 + carry;
+            *limb = v as u32;
+            carry = v >> 32;
+        }
+        if carry != 0{
+            self.0.push(carry as u32);
+        }
+    }
+    fn mul(&mut self, other: &Big){
+        let mut ret = vec![0u32; self.0.len() + other.0.len()];
+        for (i, a) in self.0.iter().enumerate(){
+            let mut carry = 0u64;
+            for (j, b) in other.0.iter().enumerate(){
+                let v = ret[i + j] as u64 + *a as u64 * *b as u64 + carry;
+                ret[i + j] = v as u32;
+                carry = v >> 32;
+            }
+            ret[i + other.0.len()] = carry as u32;
+        }
+        self.0 = ret;
+        self.trim();
+    }
+    fn trim(&mut self){
+        while self.0.last() == Some(&0){
+            self.0.pop();
+        }
+    }
+    fn bits(&self) -> usize{
+        self.0.last().map_or(0, |top| self.0.len() * 32 - top.leading_zeros() as usize)
+    }
+    fn bit(&self, i: usize) -> bool{
+        self.0.get(i / 32).is_some_and(|limb| limb >> (i % 32) & 1 != 0)
+    }
+    fn set_bit(&mut self, i: usize){
+        if self.0.len() <= i / 32{
+            self.0.resize(i / 32 + 1, 0);
+        }
+        self.0[i / 32] |= 1 << (i % 32);
+    }
+    fn shl(&self, n: usize) -> Big{
+        let mut ret = Big::default();
+        for i in 0..self.bits(){
+            if self.bit(i){
+                ret.set_bit(i + n);
+            }
+        }
+        ret
+    }
+    fn ge(&self, other: &Big) -> bool{
+        if self.0.len() != other.0.len(){
+            return self.0.len() > other.0.len();
+        }
+        self.0.iter().rev().cmp(other.0.iter().rev()) != std::cmp::Ordering::Less
+    }
+    fn sub(&mut self, other: &Big){
+        let mut borrow = 0i64;
+        for i in 0..self.0.len(){
+            let v = self.0[i] as i64 - other.0.get(i).copied().unwrap_or(0) as i64 - borrow;
+            self.0[i] = v as u32;
+            borrow = (v < 0) as i64;
+        }
+        self.trim();
+    }
+    // bit by bit long division
+    fn divmod(&self, d: &Big) -> (Big, Big){
+        let mut q = Big::default();
+        let mut r = Big::default();
+        for i in (0..self.bits()).rev(){
+            r.mul_add(2, self.bit(i) as u32);
+            if r.ge(d){
+                r.sub(d);
+                q.set_bit(i);
+            }
+        }
+        (q, r)
+    }
+}
+// __float32__(1.5) is 0x3FC00000
+pub fn float_function(word: &str) -> Option<u8>{
+    match word{
+        "__float32__" => Some(4),
+        "__float64__" => Some(8),
+        _ => None,
+    }
+}
+impl<'a> Asm<'a>{
+    // (float) after __float32__ or __float64__
+    pub fn float_function(&self, input: & 'a str, size: u8) -> Result<(& 'a str, i64), Diagnostic>{
+        let ae = self.asm_error();
+        let Some(s) = self.ignore_space(input).strip_prefix('(') else{
+            return ae.error_from_word(input, Code::RequireParen, "Require \'(\'.");
+        };
+        let s = self.ignore_space(s);
+        let Some((rest, negative, float)) = get_float(s).map_err(|(code, mes)| ae.diagnostic(s, code, mes))? else{
+            return ae.error_from_word(s, Code::RequireFloat, "Require Float.");
+        };
+        let Some(rest) = self.ignore_space(rest).strip_prefix(')') else{
+            return ae.error_from_word(input, Code::RequireParen, "Require \')\'.");
+        };
+        let mut value = [0u8; 8];
+        let bytes = float_bytes(negative, &float, size).unwrap();
+        value[..bytes.len()].copy_from_slice(&bytes);
+        Ok((rest, i64::from_le_bytes(value)))
+    }
+    // a float or a utf string in dx
+    pub fn dx_data(&self, input: & 'a str, size: u8) -> Result<Option<(& 'a str, Vec<u8>)>, Diagnostic>{
+        let ae = self.asm_error();
+        if let Some((s, negative, float)) = get_float(input).map_err(|(code, mes)| ae.diagnostic(input, code, mes))?{
+            let data = float_bytes(negative, &float, size).map_err(|(code, mes)| ae.diagnostic(input, code, mes))?;
+            return Ok(Some((s, data)));
+        }
+        let Some((s, word)) = get_word(input).ok().filter(|(_, word)| word.starts_with("__utf")) else{
+            return Ok(None);
+        };
+        let Some(s) = self.ignore_space(s).strip_prefix('(') else{
+            return ae.error_from_word(s, Code::RequireParen, "Require \'(\'.");
+        };
+        let s = self.ignore_space(s);
+        let (s, bytes) = get_quoted(s).map_err(|(code, mes)| ae.diagnostic(s, code, mes))?;
+        let Some(s) = self.ignore_space(s).strip_prefix(')') else{
+            return ae.error_from_word(s, Code::RequireParen, "Require \')\'.");
+        };
+        let data = utf(word, &bytes).map_err(|(code, mes)| ae.diagnostic(input, code, mes))?;
+        Ok(Some((s, data)))
+    }
+    // incbin "file" [, skip [, len]]
+    pub fn incbin(&self, input: & 'a str) -> Result<& 'a str, Diagnostic>{
+        let ae = self.asm_error();
+        let input = self.ignore_space(input);
+        let (mut s, name) = get_quoted(input).map_err(|(code, mes)| ae.diagnostic(input, code, mes))?;
+        let name = String::from_utf8_lossy(&name).into_owned();
+        let Some(idx) = self.incbin_file(&name) else{
+            return ae.error_from_word(input, Code::CantOpen, format!("Can't open {}.", name).as_str());
+        };
+        let size = self.files.borrow()[idx].1.len();
+        let mut skip = 0;
+        let mut len = size;
+        s = self.ignore_space(s);
+        if let Some(rest) = s.strip_prefix(','){
+            let (rest, value) = self.read_figure(rest)?;
+            skip = (value as usize).min(size);
+            s = rest;
+            if let Some(rest) = s.strip_prefix(','){
+                let (rest, value) = self.read_figure(rest)?;
+                len = value as usize;
+                s = rest;
+            }
+        }
+        let end = skip.saturating_add(len).min(size);
+        let data = self.files.borrow()[idx].1[skip..end].to_vec();
+        self.emit(data, None)?;
+        Ok(s)
+    }
+    // index in files, searched like %include
+    fn incbin_file(&self, name: &str) -> Option<usize>{
+        let mut files = self.files.borrow_mut();
+        if let Some(idx) = files.iter().position(|(n, _)| n == name){
+            return Some(idx);
+        }
+        let mut paths = vec![Path::new(name).to_path_buf()];
+        paths.extend(self.include.iter().map(|dir| Path::new(dir).join(name)));
+        let data = paths.iter().find_map(|path| fs::read(path).ok())?;
+        files.push((name.to_string(), data));
+        Some(files.len() - 1)
+    }
+}
+#[cfg(test)]
+mod tests{
+    use super::*;
+    #[test]
+    fn extended_limits(){
+        assert_eq!(extended("1", 99999999), (0x7FFF, 1 << 63));
+        assert_eq!(extended("1", -99999999), (0, 0));
+        assert_eq!(extended("12", 4931), (0x7FFF, 1 << 63));
+        // 2^-16382 is the smallest normal, 2^-16445 the smallest denormal
+        assert_eq!(extended("336210314311209350626", -4952), (1, 1 << 63));
+        assert_eq!(extended("36", -4952), (0, 1));
+        assert_eq!(extended("19", -4952), (0, 1));
+        assert_eq!(extended("18", -4952), (0, 0));
+        assert_eq!(extended("15", -1), (16383, 3 << 62));
+    }
+    #[test]
+    fn exponent_overflow(){
+        assert_eq!(get_float("1.5e-9223372036854775808").err().map(|e| e.0), Some(Code::LargeExponent));
+        assert_eq!(get_float("1e99999999999999999999").err().map(|e| e.0), Some(Code::LargeExponent));
+        assert!(matches!(get_float("1e-9223372036854775808"), Ok(Some(_))));
+    }
+}
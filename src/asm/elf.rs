@@ -0,0 +1,266 @@
+use std::io::Write;
+use std::mem;
+use super::headers::as_u8_slice;
+use super::object::{Bind, Module, ObjectFormat, Target};
+use super::{Section, SectionKind};
+use crate::diagnostic::{Code, Diagnostic};
+pub const R_X86_64_64: u32 = 1;
+pub const R_X86_64_PC32: u32 = 2;
+pub const R_X86_64_PLT32: u32 = 4;
+pub const R_X86_64_GOTPCREL: u32 = 9;
+pub const R_X86_64_32: u32 = 10;
+pub const R_X86_64_32S: u32 = 11;
+pub const R_X86_64_16: u32 = 12;
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_RELA: u32 = 4;
+const SHT_NOBITS: u32 = 8;
+const SHF_WRITE: u64 = 1;
+const SHF_ALLOC: u64 = 2;
+const SHF_EXECINSTR: u64 = 4;
+const SHF_INFO_LINK: u64 = 0x40;
+const STB_LOCAL: u8 = 0;
+const STB_GLOBAL: u8 = 1;
+const STT_NOTYPE: u8 = 0;
+const STT_OBJECT: u8 = 1;
+const STT_SECTION: u8 = 3;
+const STT_FILE: u8 = 4;
+const SHN_ABS: u16 = 0xFFF1;
+const SHN_COMMON: u16 = 0xFFF2;
+#[allow(non_camel_case_types, dead_code)]
+#[derive(Default)]
+#[repr(C)]
+pub struct ELF64_EHDR{
+    pub e_ident: [u8; 16],
+    pub e_type: u16,
+    pub e_machine: u16,
+    pub e_version: u32,
+    pub e_entry: u64,
+    pub e_phoff: u64,
+    pub e_shoff: u64,
+    pub e_flags: u32,
+    pub e_ehsize: u16,
+    pub e_phentsize: u16,
+    pub e_phnum: u16,
+    pub e_shentsize: u16,
+    pub e_shnum: u16,
+    pub e_shstrndx: u16,
+}
+impl ELF64_EHDR{
+    // ET_REL, EM_X86_64
+    pub fn new() -> Self{
+        let mut ret = Self::default();
+        ret.e_ident[..8].copy_from_slice(&[0x7F, b'E', b'L', b'F', 2, 1, 1, 0]);
+        ret.e_type = 1;
+        ret.e_machine = 0x3E;
+        ret.e_version = 1;
+        ret.e_ehsize = mem::size_of::<Self>() as u16;
+        ret.e_shentsize = mem::size_of::<ELF64_SHDR>() as u16;
+        ret
+    }
+}
+#[allow(non_camel_case_types, dead_code)]
+#[derive(Default)]
+#[repr(C)]
+pub struct ELF64_SHDR{
+    pub sh_name: u32,
+    pub sh_type: u32,
+    pub sh_flags: u64,
+    pub sh_addr: u64,
+    pub sh_offset: u64,
+    pub sh_size: u64,
+    pub sh_link: u32,
+    pub sh_info: u32,
+    pub sh_addralign: u64,
+    pub sh_entsize: u64,
+}
+#[allow(non_camel_case_types, dead_code)]
+#[derive(Default)]
+#[repr(C)]
+pub struct ELF64_SYM{
+    pub st_name: u32,
+    pub st_info: u8,
+    pub st_other: u8,
+    pub st_shndx: u16,
+    pub st_value: u64,
+    pub st_size: u64,
+}
+impl ELF64_SYM{
+    fn new(st_name: u32, bind: u8, kind: u8, st_shndx: u16, st_value: u64) -> Self{
+        Self{st_name, st_info: bind << 4 | kind, st_shndx, st_value, ..Default::default()}
+    }
+}
+#[allow(non_camel_case_types, dead_code)]
+#[derive(Default)]
+#[repr(C)]
+pub struct ELF64_RELA{
+    pub r_offset: u64,
+    pub r_info: u64,
+    pub r_addend: i64,
+}
+// .strtab and .shstrtab
+struct StrTab{
+    data: Vec<u8>,
+}
+impl StrTab{
+    fn new() -> Self{
+        Self{data: vec![0]}
+    }
+    fn add(&mut self, name: &str) -> u32{
+        let offset = self.data.len() as u32;
+        self.data.extend(name.as_bytes());
+        self.data.push(0);
+        offset
+    }
+}
+// sh_type, sh_flags
+fn section_kind(sec: &Section) -> (u32, u64){
+    let sh_type = if sec.is_nobits() {SHT_NOBITS} else {SHT_PROGBITS};
+    let mut sh_flags = if sec.kind == SectionKind::Info {0} else {SHF_ALLOC};
+    if sec.is_exec(){
+        sh_flags |= SHF_EXECINSTR;
+    }
+    if sec.is_write(){
+        sh_flags |= SHF_WRITE;
+    }
+    (sh_type, sh_flags)
+}
+fn align(data: &mut Vec<u8>, n: usize){
+    data.resize(data.len().div_ceil(n) * n, 0);
+}
+// ELF64 relocatable object
+pub struct Elf64;
+impl ObjectFormat for Elf64{
+    fn extension(&self) -> & 'static str{
+        "o"
+    }
+    // progbits, noexec, nowrite and align=1
+    fn other_section(&self) -> (SectionKind, u64){
+        (SectionKind::Rdata, 1)
+    }
+    fn write(&self, module: &Module, out: &mut dyn Write) -> Result<(), Diagnostic>{
+        let sections = module.sections;
+        let mut shstrtab = StrTab::new();
+        let mut strtab = StrTab::new();
+        let mut headers = vec![ELF64_SHDR::default()];
+        // everything after the elf header
+        let mut body = Vec::<u8>::new();
+        let offset = mem::size_of::<ELF64_EHDR>();
+        // sections
+        for sec in sections{
+            let (sh_type, sh_flags) = section_kind(sec);
+            let sh_addralign = sec.alignment();
+            align(&mut body, sh_addralign as usize);
+            let mut sh = ELF64_SHDR{sh_type, sh_flags, sh_addralign, ..Default::default()};
+            sh.sh_name = shstrtab.add(sec.name);
+            sh.sh_offset = (offset + body.len()) as u64;
+            sh.sh_size = sec.size() as u64;
+            if sh_type != SHT_NOBITS{
+                // the addends go to .rela
+                let mut data = sec.data.clone();
+                for reloc in &sec.relocations{
+                    data[reloc.offset..reloc.offset + reloc.kind.size()].fill(0);
+                }
+                body.extend(data);
+            }
+            headers.push(sh);
+        }
+        // symbols: null, file, sections, labels, externs
+        let mut symtab = Vec::<u8>::new();
+        symtab.extend(as_u8_slice(&ELF64_SYM::default()));
+        let name = strtab.add(module.file);
+        symtab.extend(as_u8_slice(&ELF64_SYM::new(name, STB_LOCAL, STT_FILE, SHN_ABS, 0)));
+        for i in 0..sections.len(){
+            let sym = ELF64_SYM::new(0, STB_LOCAL, STT_SECTION, 1 + i as u16, 0);
+            symtab.extend(as_u8_slice(&sym));
+        }
+        // the symbols start with the locals
+        let first = 2 + sections.len();
+        let locals = first + module.symbols.iter().filter(|s| s.bind == Bind::Local).count();
+        for symbol in &module.symbols{
+            let name = strtab.add(symbol.name);
+            let sym = match symbol.bind{
+                Bind::Local => ELF64_SYM::new(name, STB_LOCAL, STT_NOTYPE, symbol.section as u16, symbol.value),
+                Bind::Global | Bind::Extern =>{
+                    ELF64_SYM::new(name, STB_GLOBAL, STT_NOTYPE, symbol.section as u16, symbol.value)
+                },
+                Bind::Common(align) =>{
+                    let mut sym = ELF64_SYM::new(name, STB_GLOBAL, STT_OBJECT, SHN_COMMON, align);
+                    sym.st_size = symbol.value;
+                    sym
+                },
+            };
+            symtab.extend(as_u8_slice(&sym));
+        }
+        let symtab_idx = headers.len() + sections.iter().filter(|s| !s.relocations.is_empty()).count();
+        // .rela.*
+        for (i, sec) in sections.iter().enumerate(){
+            if sec.relocations.is_empty(){
+                continue;
+            }
+            align(&mut body, 8);
+            let mut sh = ELF64_SHDR{sh_type: SHT_RELA, sh_flags: SHF_INFO_LINK, sh_addralign: 8, ..Default::default()};
+            sh.sh_name = shstrtab.add(&format!(".rela{}", sec.name));
+            sh.sh_offset = (offset + body.len()) as u64;
+            sh.sh_entsize = mem::size_of::<ELF64_RELA>() as u64;
+            sh.sh_size = sh.sh_entsize * sec.relocations.len() as u64;
+            sh.sh_link = symtab_idx as u32;
+            sh.sh_info = 1 + i as u32;
+            for reloc in &sec.relocations{
+                let Some(r_type) = reloc.kind.elf_type() else{
+                    return Err(module.error(reloc.symbol, Code::UnsupportedRelocation, "Can't use this relocation in elf64."));
+                };
+                let idx = match module.target(reloc.symbol).unwrap(){
+                    Target::Section(i) => 2 + i,
+                    Target::Symbol(i) => first + i,
+                };
+                let rela = ELF64_RELA{
+                    r_offset: reloc.offset as u64,
+                    r_info: (idx as u64) << 32 | r_type as u64,
+                    r_addend: reloc.addend(&sec.data) - reloc.kind.pc_bias(),
+                };
+                body.extend(as_u8_slice(&rela));
+            }
+            headers.push(sh);
+        }
+        // .symtab
+        align(&mut body, 8);
+        let mut sh = ELF64_SHDR{sh_type: SHT_SYMTAB, sh_addralign: 8, ..Default::default()};
+        sh.sh_name = shstrtab.add(".symtab");
+        sh.sh_offset = (offset + body.len()) as u64;
+        sh.sh_size = symtab.len() as u64;
+        sh.sh_link = symtab_idx as u32 + 1;
+        sh.sh_info = locals as u32;
+        sh.sh_entsize = mem::size_of::<ELF64_SYM>() as u64;
+        body.extend(symtab);
+        headers.push(sh);
+        // .strtab
+        let mut sh = ELF64_SHDR{sh_type: SHT_STRTAB, sh_addralign: 1, ..Default::default()};
+        sh.sh_name = shstrtab.add(".strtab");
+        sh.sh_offset = (offset + body.len()) as u64;
+        sh.sh_size = strtab.data.len() as u64;
+        body.extend(strtab.data);
+        headers.push(sh);
+        // .shstrtab
+        let mut sh = ELF64_SHDR{sh_type: SHT_STRTAB, sh_addralign: 1, ..Default::default()};
+        sh.sh_name = shstrtab.add(".shstrtab");
+        sh.sh_offset = (offset + body.len()) as u64;
+        sh.sh_size = shstrtab.data.len() as u64;
+        body.extend(shstrtab.data);
+        headers.push(sh);
+        // section headers
+        align(&mut body, 8);
+        let mut ehdr = ELF64_EHDR::new();
+        ehdr.e_shoff = (offset + body.len()) as u64;
+        ehdr.e_shnum = headers.len() as u16;
+        ehdr.e_shstrndx = headers.len() as u16 - 1;
+        // * Writing
+        out.write_all(as_u8_slice(&ehdr))?;
+        out.write_all(&body)?;
+        for sh in &headers{
+            out.write_all(as_u8_slice(sh))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,104 @@
+use super::reg::{self as r, Value};
+use super::reloc::{Relocation, RelocKind};
+use crate::diagnostic::{Code, Error};
+// modr/m.reg holds a register or /digit
+#[derive(Clone, Copy)]
+pub enum RegField{
+    Reg(u8, u8),
+    Digit(u8),
+}
+// [66] [rex] opcode
+// size 2 adds the operand size prefix, size 8 sets REX.W
+fn prefix(data: &mut Vec<u8>, size: u8, rex: (u8, u8, u8), force_rex: bool, high: bool) -> Result<(), Error>{
+    if size == 2{
+        data.push(0x66);
+    }
+    let w = (size == 8) as u8;
+    let (rexr, rexx, rexb) = rex;
+    if w | rexr | rexx | rexb != 0 || force_rex{
+        if high{
+            return Err((Code::HighByteRex, "Can't use ah, ch, dh or bh with REX prefix."));
+        }
+        data.push(r::create_rex(w, rexr, rexx, rexb));
+    }
+    Ok(())
+}
+// opcode without operands in modr/m (accumulator forms)
+pub fn encode_op(op: &[u8], size: u8) -> Vec<u8>{
+    let mut data = Vec::<u8>::new();
+    // can't fail without registers
+    let _ = prefix(&mut data, size, (0, 0, 0), false, false);
+    data.extend(op);
+    data
+}
+// opcode + rd
+pub fn encode_plus_r(op: &[u8], size: u8, reg: u8) -> Result<Vec<u8>, Error>{
+    let mut data = Vec::<u8>::new();
+    let rexb = (reg >> 3) & 1;
+    prefix(&mut data, size, (0, 0, rexb), r::needs_rex(reg, size), r::is_high(reg))?;
+    let (last, op) = op.split_last().expect("");
+    data.extend(op);
+    data.push(last + (reg & 0b111));
+    Ok(data)
+}
+// [66] [rex] opcode modr/m [sib] [disp]
+pub fn encode_rm<'a>(op: &[u8], size: u8, reg: RegField, rm: &Value<'a>)
+    -> Result<(Vec<u8>, Option<Relocation<'a>>), Error>{
+    let mut data = Vec::<u8>::new();
+    let (reg, mut force_rex, mut high) = match reg{
+        RegField::Reg(reg, reg_size) => (reg, r::needs_rex(reg, reg_size), r::is_high(reg)),
+        RegField::Digit(digit) => (digit, false, false),
+    };
+    let rexr = (reg >> 3) & 1;
+    let (rexx, rexb) = match rm{
+        Value::Reg(rm, rm_size) =>{
+            force_rex |= r::needs_rex(*rm, *rm_size);
+            high |= r::is_high(*rm);
+            (0, (rm >> 3) & 1)
+        },
+        Value::Mem(mem) => (mem.rex_x(), mem.rex_b()),
+        _ => return Err((Code::RequireRegister, "Expect Register or Memory.")),
+    };
+    prefix(&mut data, size, (rexr, rexx, rexb), force_rex, high)?;
+    data.extend(op);
+    let mut reloc = None;
+    match rm{
+        Value::Reg(rm, _) => data.push(r::create_modrm(0b11, reg & 0b111, rm & 0b111)),
+        Value::Mem(mem) =>{
+            data.extend(mem.encode(reg));
+            // disp32 is the last field
+            reloc = mem.label.map(|symbol| {
+                let kind = if mem.got{
+                    RelocKind::GotPcRel(0)
+                }else if mem.rel{
+                    RelocKind::Rel32(0)
+                }else{
+                    // disp32 is sign extended to 64 bits
+                    RelocKind::Addr32S
+                };
+                Relocation::new(data.len() - 4, symbol, kind)
+            });
+        },
+        _ =>{},
+    }
+    Ok((data, reloc))
+}
+// value fits size bytes as unsigned or sign extended
+pub fn fits(value: u64, size: u8) -> bool{
+    if size >= 8{
+        return true;
+    }
+    let bits = size as u32 * 8;
+    value < (1u64 << bits) || (-(1i64 << (bits - 1))..0).contains(&(value as i64))
+}
+pub fn sign_extend(value: u64, size: u8) -> i64{
+    if size >= 8{
+        return value as i64;
+    }
+    let bits = 64 - size as u32 * 8;
+    ((value << bits) as i64) >> bits
+}
+// sign extended imm8
+pub fn fits_imm8(value: u64, size: u8) -> bool{
+    (-0x80..0x80).contains(&sign_extend(value, size))
+}
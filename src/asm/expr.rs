@@ -0,0 +1,327 @@
+use super::{Asm, get_word, get_str_back, get_str_first};
+use crate::diagnostic::{Code, Diagnostic, Error};
+use super::reg::{self as r, Value};
+use super::data::{float_function, get_quoted};
+// binary operators from the lowest precedence.
+// / and % are unsigned, // and %% are signed
+const OPERATORS: [&[&str]; 10] = [
+    &["||"],
+    &["^^"],
+    &["&&"],
+    &["==", "!=", "<>", "<=", ">=", "=", "<", ">"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["//", "%%", "*", "/", "%"],
+];
+// value of an operand: absolute if label is None,
+// otherwise relocatable against the label with value as the addend
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct Expr<'a>{
+    pub value: i64,
+    // a label, extern or section name ($, $$)
+    pub label: Option<& 'a str>,
+}
+#[derive(Clone, Copy)]
+enum Base<'a>{
+    // register and size, only in [mem]
+    Reg(u8, u8),
+    // section number and a symbol of the section with its position
+    Section(usize, & 'a str, i64),
+    // extern or label not defined yet
+    Symbol(& 'a str),
+}
+impl Base<'_>{
+    fn same(&self, other: &Base) -> bool{
+        match (self, other){
+            (Base::Reg(a, _), Base::Reg(b, _)) => a == b,
+            (Base::Section(a, _, _), Base::Section(b, _, _)) => a == b,
+            (Base::Symbol(a), Base::Symbol(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+// value + coefficient * base + ...
+// label differences in the same section cancel out
+#[derive(Clone, Default)]
+pub struct Linear<'a>{
+    value: i64,
+    terms: Vec<(Base<'a>, i64)>,
+}
+impl<'a> Linear<'a>{
+    fn constant(value: i64) -> Self{
+        Self{value, terms: Vec::new()}
+    }
+    fn term(value: i64, base: Base<'a>) -> Self{
+        Self{value, terms: vec![(base, 1)]}
+    }
+    // None if a coefficient overflows, the value wraps
+    fn add(mut self, other: Linear<'a>, sign: i64) -> Option<Self>{
+        self.value = self.value.wrapping_add(other.value.wrapping_mul(sign));
+        for (base, coef) in other.terms{
+            let coef = coef.checked_mul(sign)?;
+            match self.terms.iter_mut().find(|(b, _)| b.same(&base)){
+                Some((_, c)) => *c = c.checked_add(coef)?,
+                None => self.terms.push((base, coef)),
+            }
+        }
+        self.terms.retain(|(_, c)| *c != 0);
+        Some(self)
+    }
+    fn scale(mut self, k: i64) -> Option<Self>{
+        self.value = self.value.wrapping_mul(k);
+        for (_, coef) in self.terms.iter_mut(){
+            *coef = coef.checked_mul(k)?;
+        }
+        self.terms.retain(|(_, c)| *c != 0);
+        Some(self)
+    }
+    fn absolute(&self) -> Option<i64>{
+        self.terms.is_empty().then_some(self.value)
+    }
+    // registers with their size and scale, in order of appearance
+    pub fn take_regs(&mut self) -> Vec<(u8, u8, i64)>{
+        let regs = self.terms.iter().filter_map(|(base, coef)| match base{
+            Base::Reg(reg, size) => Some((*reg, *size, *coef)),
+            _ => None,
+        }).collect();
+        self.terms.retain(|(base, _)| !matches!(base, Base::Reg(..)));
+        regs
+    }
+    pub fn to_expr(&self) -> Result<Expr<'a>, Error>{
+        match self.terms.as_slice(){
+            [] => Ok(Expr{value: self.value, label: None}),
+            [(Base::Section(_, name, pos), 1)] => Ok(Expr{value: self.value.wrapping_sub(*pos), label: Some(name)}),
+            [(Base::Symbol(name), 1)] => Ok(Expr{value: self.value, label: Some(name)}),
+            _ if self.terms.iter().any(|(base, _)| matches!(base, Base::Reg(..))) =>{
+                Err((Code::RegisterNotAllowed, "Register can't be used here."))
+            },
+            _ => Err((Code::NotRelocatable, "Expression is not relocatable.")),
+        }
+    }
+}
+impl<'a> Asm<'a>{
+    // expressions start with a figure, label, $, quote, ( or unary operator
+    pub fn is_expr(input: &str) -> bool{
+        !input.is_empty() && (get_word(input).is_ok() || b"0123456789$'\"`(-+~!".contains(&get_str_first(input)))
+    }
+    pub fn read_expr(&self, input: & 'a str) -> Result<(& 'a str, Expr<'a>), Diagnostic>{
+        let (s, linear) = self.read_linear(input)?;
+        match linear.to_expr(){
+            Ok(expr) => Ok((s, expr)),
+            Err((code, mes)) if linear.terms.iter().any(|(base, _)| matches!(base, Base::Reg(..))) =>{
+                let ae = self.asm_error();
+                ae.error_from_word(input, code, mes)
+            },
+            // labels may move to the same section in a later pass
+            Err((code, mes)) =>{
+                self.defer_error(input, code, mes);
+                Ok((s, Expr::default()))
+            },
+        }
+    }
+    // the value of an absolute expression, 0 until labels settle
+    pub fn absolute(&self, expr: Expr<'a>, word: & 'a str) -> i64{
+        if expr.label.is_some(){
+            self.defer_error(word, Code::NotAbsolute, "Expression is not absolute.");
+            return 0;
+        }
+        expr.value
+    }
+    pub fn read_linear(&self, input: & 'a str) -> Result<(& 'a str, Linear<'a>), Diagnostic>{
+        self.binary(input, 0)
+    }
+    fn binary(&self, input: & 'a str, level: usize) -> Result<(& 'a str, Linear<'a>), Diagnostic>{
+        if level == OPERATORS.len(){
+            return self.unary(input);
+        }
+        let (mut input, mut lhs) = self.binary(input, level + 1)?;
+        loop{
+            let s = self.ignore_space(input);
+            // | is not ||
+            let Some(op) = OPERATORS[level].iter().find(|op| {
+                s.starts_with(**op) && !(matches!(**op, "|" | "^" | "&") && s[1..].starts_with(**op))
+            }) else{
+                return Ok((input, lhs));
+            };
+            let word = s;
+            let (s, rhs) = self.binary(self.ignore_space(&s[op.len()..]), level + 1)?;
+            input = s;
+            lhs = self.operate(op, lhs, rhs, word);
+        }
+    }
+    fn operate(&self, op: &str, lhs: Linear<'a>, rhs: Linear<'a>, word: & 'a str) -> Linear<'a>{
+        match op{
+            "+" => return lhs.add(rhs, 1).unwrap_or_else(|| self.not_simple(word)),
+            "-" => return lhs.add(rhs, -1).unwrap_or_else(|| self.not_simple(word)),
+            "*" =>{
+                if let Some(k) = lhs.absolute(){
+                    return rhs.scale(k).unwrap_or_else(|| self.not_simple(word));
+                }
+                if let Some(k) = rhs.absolute(){
+                    return lhs.scale(k).unwrap_or_else(|| self.not_simple(word));
+                }
+            },
+            _ =>{},
+        }
+        let (Some(a), Some(b)) = (lhs.absolute(), rhs.absolute()) else{
+            return self.not_simple(word);
+        };
+        if matches!(op, "/" | "%" | "//" | "%%") && b == 0{
+            self.defer_error(word, Code::DivisionByZero, "Division by zero.");
+            return Linear::default();
+        }
+        let value = match op{
+            "/" => (a as u64 / b as u64) as i64,
+            "%" => (a as u64 % b as u64) as i64,
+            "//" => a.wrapping_div(b),
+            "%%" => a.wrapping_rem(b),
+            "<<" => a.checked_shl(b as u32).filter(|_| (0..64).contains(&b)).unwrap_or(0),
+            ">>" => (a as u64).checked_shr(b as u32).filter(|_| (0..64).contains(&b)).unwrap_or(0) as i64,
+            "&" => a & b,
+            "|" => a | b,
+            "^" => a ^ b,
+            "==" | "=" => (a == b) as i64,
+            "!=" | "<>" => (a != b) as i64,
+            "<" => (a < b) as i64,
+            "<=" => (a <= b) as i64,
+            ">" => (a > b) as i64,
+            ">=" => (a >= b) as i64,
+            "&&" => (a != 0 && b != 0) as i64,
+            "||" => (a != 0 || b != 0) as i64,
+            "^^" => ((a != 0) != (b != 0)) as i64,
+            _ => a.wrapping_mul(b),
+        };
+        Linear::constant(value)
+    }
+    // 0 and an error after the last pass
+    fn not_simple(&self, word: & 'a str) -> Linear<'a>{
+        self.defer_error(word, Code::NotSimple, "Expression is not simple.");
+        Linear::default()
+    }
+    // - + ~ ! primary
+    fn unary(&self, input: & 'a str) -> Result<(& 'a str, Linear<'a>), Diagnostic>{
+        let input = self.ignore_space(input);
+        let Some(op) = input.chars().next().filter(|c| "-+~!".contains(*c)) else{
+            return self.primary(input);
+        };
+        let (s, value) = self.unary(&input[1..])?;
+        let value = match op{
+            '-' => value.scale(-1).unwrap_or_else(|| self.not_simple(input)),
+            '+' => value,
+            _ =>{
+                let Some(v) = value.absolute() else{
+                    return Ok((s, self.not_simple(input)));
+                };
+                Linear::constant(if op == '~' {!v} else {(v == 0) as i64})
+            },
+        };
+        Ok((s, value))
+    }
+    // figure, character, $, $$, label, register or (expr)
+    fn primary(&self, input: & 'a str) -> Result<(& 'a str, Linear<'a>), Diagnostic>{
+        let ae = self.asm_error();
+        let c = get_str_first(input);
+        if let Some(s) = input.strip_prefix('('){
+            let (s, value) = self.read_linear(s)?;
+            let s = self.ignore_space(s);
+            // points the unclosed (
+            let Some(s) = s.strip_prefix(')') else{
+                return ae.error_from_word(input, Code::RequireParen, "Require \')\'.");
+            };
+            return Ok((s, value));
+        }
+        if let Some(s) = input.strip_prefix("$$"){
+            let section = self.section()?;
+            return Ok((s, Linear::term(0, Base::Section(self.current.get(), section.name, 0))));
+        }
+        if let Some(s) = input.strip_prefix('$'){
+            // $0ff is a figure
+            if !s.starts_with(|c: char| c.is_ascii_digit()){
+                let section = self.section()?;
+                let here = self.here.get() as i64;
+                return Ok((s, Linear::term(here, Base::Section(self.current.get(), section.name, 0))));
+            }
+        }
+        if c.is_ascii_digit() || c == b'$'{
+            let (s, value) = get_number(input).map_err(|(code, mes)| ae.diagnostic(input, code, mes))?;
+            return Ok((s, Linear::constant(value as i64)));
+        }
+        if c == b'\'' || c == b'"' || c == b'`'{
+            let (s, value) = get_character(input).map_err(|(code, mes)| ae.diagnostic(input, code, mes))?;
+            return Ok((s, Linear::constant(value as i64)));
+        }
+        let Ok((s, word)) = get_word(input) else{
+            return ae.error_from_word(input, Code::RequireOperand, "Expect Figure or Label.");
+        };
+        if let Some(size) = float_function(word){
+            let (s, value) = self.float_function(s, size)?;
+            return Ok((s, Linear::constant(value)));
+        }
+        if let Ok(Value::Reg(reg, size)) = r::reg(word){
+            return Ok((s, Linear::term(0, Base::Reg(reg, size))));
+        }
+        if let Some(value) = self.constant(word){
+            return Ok((s, value));
+        }
+        Ok((s, self.expr_linear(Expr{value: 0, label: Some(word)})))
+    }
+    // label + addend, a defined label cancels out with the same section
+    pub fn expr_linear(&self, expr: Expr<'a>) -> Linear<'a>{
+        let Some(label) = expr.label else{
+            return Linear::constant(expr.value);
+        };
+        match self.find_label(label){
+            Some((section_number, pos)) =>{
+                Linear::term(pos as i64 + expr.value, Base::Section(section_number, label, pos as i64))
+            },
+            None => Linear::term(expr.value, Base::Symbol(label)),
+        }
+    }
+}
+// 0x1f 1fh 0b101 101b 0o17 17q 0d10 10d $1f, _ separates digits
+pub fn get_number(input: &str) -> Result<(&str, u64), Error>{
+    let first = input;
+    let input = input.strip_prefix('$').unwrap_or(input);
+    let len = input.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(input.len());
+    let token = get_str_back(first, &input[len..]);
+    let digits = input[..len].replace('_', "").to_lowercase();
+    let (radix, digits) = if token.starts_with('$'){
+        (16, digits.as_str())
+    }else if let Some(d) = digits.strip_suffix('h'){
+        (16, d)
+    }else if digits.len() > 2 && digits.starts_with('0') && digits.as_bytes()[1].is_ascii_alphabetic(){
+        let radix = match digits.as_bytes()[1]{
+            b'x' | b'h' => 16,
+            b'b' | b'y' => 2,
+            b'o' | b'q' => 8,
+            b'd' | b't' => 10,
+            _ => return Err((Code::InvalidFigure, "Invalid figure.")),
+        };
+        (radix, &digits[2..])
+    }else{
+        match digits.as_bytes()[digits.len() - 1]{
+            b'b' | b'y' => (2, &digits[..digits.len() - 1]),
+            b'o' | b'q' => (8, &digits[..digits.len() - 1]),
+            b'd' | b't' => (10, &digits[..digits.len() - 1]),
+            _ => (10, digits.as_str()),
+        }
+    };
+    match u64::from_str_radix(digits, radix){
+        Ok(value) => Ok((&input[len..], value)),
+        Err(e) if *e.kind() == std::num::IntErrorKind::PosOverflow => Err((Code::TooLargeFigure, "Too large figure.")),
+        Err(_) => Err((Code::InvalidFigure, "Invalid figure.")),
+    }
+}
+// 'ab' is 0x6261
+fn get_character(input: &str) -> Result<(&str, u64), Error>{
+    let (s, bytes) = get_quoted(input)?;
+    if bytes.len() > 8{
+        return Err((Code::CharacterTooLong, "Character constant is too long."));
+    }
+    let mut value = [0u8; 8];
+    value[..bytes.len()].copy_from_slice(&bytes);
+    Ok((s, u64::from_le_bytes(value)))
+}
@@ -32,6 +32,21 @@ pub struct SECTION_HEADER{
     pub NumberOfLinenumbers: u16,
     pub Characteristics: u32,
 }
+pub const IMAGE_SCN_CNT_CODE: u32 = 0x0000_0020;
+pub const IMAGE_SCN_CNT_INITIALIZED_DATA: u32 = 0x0000_0040;
+pub const IMAGE_SCN_CNT_UNINITIALIZED_DATA: u32 = 0x0000_0080;
+pub const IMAGE_SCN_LNK_INFO: u32 = 0x0000_0200;
+pub const IMAGE_SCN_LNK_REMOVE: u32 = 0x0000_0800;
+pub const IMAGE_SCN_ALIGN_1BYTES: u32 = 0x0010_0000;// ALIGN_2BYTES..ALIGN_8192BYTES follow
+pub const IMAGE_SCN_MEM_EXECUTE: u32 = 0x2000_0000;
+pub const IMAGE_SCN_MEM_READ: u32 = 0x4000_0000;
+pub const IMAGE_SCN_MEM_WRITE: u32 = 0x8000_0000;
+pub const IMAGE_REL_AMD64_ADDR64: u16 = 0x0001;
+pub const IMAGE_REL_AMD64_ADDR32: u16 = 0x0002;
+pub const IMAGE_REL_AMD64_ADDR32NB: u16 = 0x0003;
+pub const IMAGE_REL_AMD64_REL32: u16 = 0x0004;// REL32_1..5 follow
+pub const IMAGE_REL_AMD64_SECTION: u16 = 0x000A;
+pub const IMAGE_REL_AMD64_SECREL: u16 = 0x000B;
 #[allow(non_camel_case_types, non_snake_case)]
 #[derive(Default)]
 #[repr(packed)]
@@ -86,12 +101,3 @@ pub fn as_u8_slice<T: Sized>(p: &T) -> &[u8]{
     }
 }
 
-pub fn as_u8_slice_size<T: Sized>(p: &T,size :usize)-> &[u8]{
-    unsafe{
-    std::slice::from_raw_parts(
-        (p as * const T) as *const u8,
-        size,
-    )
-    }
-}
-
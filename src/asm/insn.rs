@@ -0,0 +1,463 @@
+use std::sync::OnceLock;
+use super::reg::{self as r, Value};
+use super::reloc::{Relocation, RelocKind};
+use super::encode::*;
+use super::CONDITIONS;
+use super::expr::Expr;
+use crate::diagnostic::{Code, Error};
+// operand pattern. size 0 is the operand size of the form
+#[derive(Clone, Copy, PartialEq)]
+pub enum Op{
+    R(u8),
+    Rm(u8),
+    // memory of any size (lea)
+    M,
+    // al, ax, eax, rax
+    Acc,
+    Cl,
+    // figure 1 (shift by 1)
+    One,
+    Imm8,
+    // imm8 sign extended to the operand size
+    Imm8S,
+    // imm of the operand size, imm32 sign extended for 64bit
+    Imm,
+    Imm16,
+    // imm32 zero extended to 64bit
+    ImmU32,
+    Imm64,
+}
+#[derive(Clone, Copy, PartialEq)]
+pub enum ModRm{
+    None,
+    // /r
+    R,
+    // /digit
+    Digit(u8),
+    // +r
+    PlusR,
+}
+pub struct Insn{
+    pub name: String,
+    pub ops: & 'static [Op],
+    // operand sizes: 2 adds 0x66, 8 sets REX.W unless d64
+    pub sizes: & 'static [u8],
+    pub opcode: Vec<u8>,
+    pub modrm: ModRm,
+    // 64bit by default, no REX.W
+    pub d64: bool,
+}
+impl Insn{
+    fn d64(mut self) -> Self{
+        self.d64 = true;
+        self
+    }
+}
+fn insn(name: &str, ops: & 'static [Op], sizes: & 'static [u8], opcode: &[u8], modrm: ModRm) -> Insn{
+    Insn{name: name.to_string(), ops, sizes, opcode: opcode.to_vec(), modrm, d64: false}
+}
+use Op::*;
+const B: &[u8] = &[1];
+const W: &[u8] = &[2, 4, 8];
+const Q: &[u8] = &[8];
+// the general purpose instructions
+fn database() -> Vec<Insn>{
+    let mut db = Vec::<Insn>::new();
+    let alu = ["add", "or", "adc", "sbb", "and", "sub", "xor", "cmp"];
+    for (digit, name) in alu.into_iter().enumerate(){
+        let digit = digit as u8;
+        let base = digit << 3;
+        db.extend([
+            insn(name, &[Rm(0), R(0)], B, &[base], ModRm::R),
+            insn(name, &[Rm(0), R(0)], W, &[base | 1], ModRm::R),
+            insn(name, &[R(0), Rm(0)], B, &[base | 2], ModRm::R),
+            insn(name, &[R(0), Rm(0)], W, &[base | 3], ModRm::R),
+            // imm8 wins over the accumulator form of the same length
+            insn(name, &[Rm(0), Imm8S], W, &[0x83], ModRm::Digit(digit)),
+            insn(name, &[Acc, Imm], B, &[base | 4], ModRm::None),
+            insn(name, &[Acc, Imm], W, &[base | 5], ModRm::None),
+            insn(name, &[Rm(0), Imm], B, &[0x80], ModRm::Digit(digit)),
+            insn(name, &[Rm(0), Imm], W, &[0x81], ModRm::Digit(digit)),
+        ]);
+    }
+    // no operands, the size gives 66 or REX.W
+    let plain: [(&str, u8, &[u8]); 38] = [
+        ("leave", 0, &[0xC9]), ("syscall", 0, &[0x0F, 0x05]), ("hlt", 0, &[0xF4]), ("int3", 0, &[0xCC]),
+        ("ud2", 0, &[0x0F, 0x0B]), ("pause", 0, &[0xF3, 0x90]), ("cpuid", 0, &[0x0F, 0xA2]), ("rdtsc", 0, &[0x0F, 0x31]),
+        ("clc", 0, &[0xF8]), ("stc", 0, &[0xF9]), ("cmc", 0, &[0xF5]), ("cld", 0, &[0xFC]), ("std", 0, &[0xFD]),
+        ("cli", 0, &[0xFA]), ("sti", 0, &[0xFB]),
+        ("lfence", 0, &[0x0F, 0xAE, 0xE8]), ("mfence", 0, &[0x0F, 0xAE, 0xF0]), ("sfence", 0, &[0x0F, 0xAE, 0xF8]),
+        ("pushfq", 0, &[0x9C]), ("popfq", 0, &[0x9D]),
+        ("cbw", 2, &[0x98]), ("cwde", 4, &[0x98]), ("cdqe", 8, &[0x98]),
+        ("cwd", 2, &[0x99]), ("cdq", 4, &[0x99]), ("cqo", 8, &[0x99]),
+        ("movsb", 1, &[0xA4]), ("movsw", 2, &[0xA5]), ("movsd", 4, &[0xA5]), ("movsq", 8, &[0xA5]),
+        ("stosb", 1, &[0xAA]), ("stosw", 2, &[0xAB]), ("stosd", 4, &[0xAB]), ("stosq", 8, &[0xAB]),
+        ("lodsb", 1, &[0xAC]), ("lodsq", 8, &[0xAD]), ("scasb", 1, &[0xAE]), ("cmpsb", 1, &[0xA6]),
+    ];
+    for (name, size, opcode) in plain{
+        let sizes: & 'static [u8] = match size{1 => B, 2 => &[2], 4 => &[4], 8 => Q, _ => &[0]};
+        db.push(insn(name, &[], sizes, opcode, ModRm::None));
+    }
+    db.extend([
+        insn("int", &[Imm8], &[0], &[0xCD], ModRm::None),
+        insn("enter", &[Imm16, Imm8], &[0], &[0xC8], ModRm::None),
+        insn("test", &[Rm(0), R(0)], B, &[0x84], ModRm::R),
+        insn("test", &[Rm(0), R(0)], W, &[0x85], ModRm::R),
+        insn("test", &[R(0), Rm(0)], B, &[0x84], ModRm::R),
+        insn("test", &[R(0), Rm(0)], W, &[0x85], ModRm::R),
+        insn("test", &[Acc, Imm], B, &[0xA8], ModRm::None),
+        insn("test", &[Acc, Imm], W, &[0xA9], ModRm::None),
+        insn("test", &[Rm(0), Imm], B, &[0xF6], ModRm::Digit(0)),
+        insn("test", &[Rm(0), Imm], W, &[0xF7], ModRm::Digit(0)),
+
+        insn("mov", &[Rm(0), R(0)], B, &[0x88], ModRm::R),
+        insn("mov", &[Rm(0), R(0)], W, &[0x89], ModRm::R),
+        insn("mov", &[R(0), Rm(0)], B, &[0x8A], ModRm::R),
+        insn("mov", &[R(0), Rm(0)], W, &[0x8B], ModRm::R),
+        insn("mov", &[R(0), Imm], B, &[0xB0], ModRm::PlusR),
+        insn("mov", &[R(0), Imm], &[2, 4], &[0xB8], ModRm::PlusR),
+        // mov r32 zero extends to r64
+        insn("mov", &[R(8), ImmU32], &[4], &[0xB8], ModRm::PlusR),
+        insn("mov", &[R(0), Imm64], Q, &[0xB8], ModRm::PlusR),
+        insn("mov", &[Rm(0), Imm], B, &[0xC6], ModRm::Digit(0)),
+        insn("mov", &[Rm(0), Imm], W, &[0xC7], ModRm::Digit(0)),
+
+        insn("nop", &[], &[0], &[0x90], ModRm::None),
+        insn("nop", &[Rm(0)], W, &[0x0F, 0x1F], ModRm::Digit(0)),
+        insn("ret", &[], &[0], &[0xC3], ModRm::None),
+        insn("ret", &[Imm16], &[0], &[0xC2], ModRm::None),
+        insn("jmp", &[Rm(8)], Q, &[0xFF], ModRm::Digit(4)).d64(),
+        insn("call", &[Rm(8)], Q, &[0xFF], ModRm::Digit(2)).d64(),
+
+        insn("push", &[R(0)], &[2, 8], &[0x50], ModRm::PlusR).d64(),
+        insn("push", &[Rm(0)], &[2, 8], &[0xFF], ModRm::Digit(6)).d64(),
+        insn("push", &[Imm8S], Q, &[0x6A], ModRm::None).d64(),
+        insn("push", &[Imm], Q, &[0x68], ModRm::None).d64(),
+        insn("pop", &[R(0)], &[2, 8], &[0x58], ModRm::PlusR).d64(),
+        insn("pop", &[Rm(0)], &[2, 8], &[0x8F], ModRm::Digit(0)).d64(),
+        insn("lea", &[R(0), M], W, &[0x8D], ModRm::R),
+        // 90 + r, except xchg eax, eax (90 is nop)
+        insn("xchg", &[Acc, R(0)], W, &[0x90], ModRm::PlusR),
+        insn("xchg", &[R(0), Acc], W, &[0x90], ModRm::PlusR),
+        insn("xchg", &[Rm(0), R(0)], B, &[0x86], ModRm::R),
+        insn("xchg", &[Rm(0), R(0)], W, &[0x87], ModRm::R),
+        insn("xchg", &[R(0), Rm(0)], B, &[0x86], ModRm::R),
+        insn("xchg", &[R(0), Rm(0)], W, &[0x87], ModRm::R),
+        insn("movzx", &[R(0), Rm(1)], W, &[0x0F, 0xB6], ModRm::R),
+        insn("movzx", &[R(0), Rm(2)], &[4, 8], &[0x0F, 0xB7], ModRm::R),
+        insn("movsx", &[R(0), Rm(1)], W, &[0x0F, 0xBE], ModRm::R),
+        insn("movsx", &[R(0), Rm(2)], &[4, 8], &[0x0F, 0xBF], ModRm::R),
+        insn("movsxd", &[R(0), Rm(4)], Q, &[0x63], ModRm::R),
+    ]);
+    // shift and rotate
+    let shift = [("rol", 0), ("ror", 1), ("rcl", 2), ("rcr", 3), ("shl", 4), ("sal", 4), ("shr", 5), ("sar", 7)];
+    for (name, digit) in shift{
+        db.extend([
+            insn(name, &[Rm(0), One], B, &[0xD0], ModRm::Digit(digit)),
+            insn(name, &[Rm(0), One], W, &[0xD1], ModRm::Digit(digit)),
+            insn(name, &[Rm(0), Cl], B, &[0xD2], ModRm::Digit(digit)),
+            insn(name, &[Rm(0), Cl], W, &[0xD3], ModRm::Digit(digit)),
+            insn(name, &[Rm(0), Imm8], B, &[0xC0], ModRm::Digit(digit)),
+            insn(name, &[Rm(0), Imm8], W, &[0xC1], ModRm::Digit(digit)),
+        ]);
+    }
+    // unary group 3 and 4/5
+    let unary = [("not", 0xF6, 2), ("neg", 0xF6, 3), ("mul", 0xF6, 4), ("imul", 0xF6, 5),
+        ("div", 0xF6, 6), ("idiv", 0xF6, 7), ("inc", 0xFE, 0), ("dec", 0xFE, 1)];
+    for (name, op, digit) in unary{
+        db.extend([
+            insn(name, &[Rm(0)], B, &[op], ModRm::Digit(digit)),
+            insn(name, &[Rm(0)], W, &[op | 1], ModRm::Digit(digit)),
+        ]);
+    }
+    db.extend([
+        insn("imul", &[R(0), Rm(0)], W, &[0x0F, 0xAF], ModRm::R),
+        insn("imul", &[R(0), Rm(0), Imm8S], W, &[0x6B], ModRm::R),
+        insn("imul", &[R(0), Rm(0), Imm], W, &[0x69], ModRm::R),
+        insn("imul", &[R(0), Imm8S], W, &[0x6B], ModRm::R),
+        insn("imul", &[R(0), Imm], W, &[0x69], ModRm::R),
+    ]);
+    for (cc, tttn) in CONDITIONS{
+        db.extend([
+            insn(&format!("cmov{}", cc), &[R(0), Rm(0)], W, &[0x0F, 0x40 | tttn], ModRm::R),
+            insn(&format!("set{}", cc), &[Rm(0)], B, &[0x0F, 0x90 | tttn], ModRm::Digit(0)),
+        ]);
+    }
+    db
+}
+static DB: OnceLock<Vec<Insn>> = OnceLock::new();
+pub fn find(name: &str) -> impl Iterator<Item = & 'static Insn> + '_{
+    DB.get_or_init(database).iter().filter(move |insn| insn.name == name)
+}
+pub fn exists(name: &str) -> bool{
+    find(name).next().is_some()
+}
+fn figure(value: &Value) -> Option<u64>{
+    match value{
+        Value::Imm(Expr{value, label: None}) => Some(*value as u64),
+        _ => None,
+    }
+}
+// Some(size given to a memory operand without size) if op accepts value
+fn match_op(op: Op, value: &Value, size: u8) -> Option<Option<u8>>{
+    let want = |n: u8| if n == 0 {size} else {n};
+    let yes = Some(None);
+    match (op, value){
+        (R(n) | Rm(n), Value::Reg(_, s)) if *s == want(n) => yes,
+        (Rm(n), Value::Mem(mem)) if mem.size == want(n) => yes,
+        (Rm(n), Value::Mem(mem)) if mem.size == 0 => Some(Some(want(n))),
+        (M, Value::Mem(_)) => yes,
+        (Acc, Value::Reg(r::RAX, s)) if *s == size => yes,
+        (Cl, Value::Reg(r::RCX, 1)) => yes,
+        (One, _) if figure(value) == Some(1) => yes,
+        (Imm8, _) => figure(value).filter(|v| fits(*v, 1)).and(yes),
+        (Imm8S, _) => figure(value).filter(|v| fits(*v, size) && fits_imm8(*v, size)).and(yes),
+        (Imm, Value::Imm(Expr{label: Some(_), ..})) if size == 4 => yes,
+        (Imm, _) => figure(value).filter(|v| {
+            if size == 8 {
+                // 0x8000_0000 .. 0xffff_ffff is sign extended with a warning
+                (-0x8000_0000..0x1_0000_0000).contains(&(*v as i64))
+            }else{
+                fits(*v, size)
+            }
+        }).and(yes),
+        (Imm16, _) => figure(value).filter(|v| fits(*v, 2)).and(yes),
+        (ImmU32, _) => figure(value).filter(|v| *v <= 0xffff_ffff).and(yes),
+        (Imm64, Value::Imm(Expr{label: Some(_), ..})) => yes,
+        (Imm64, _) => figure(value).and(yes),
+        _ => None,
+    }
+}
+// size given to an unsized memory operand, if the form fits
+pub fn match_insn(insn: &Insn, size: u8, values: &[Value]) -> Option<Option<u8>>{
+    if insn.ops.len() != values.len(){
+        return None;
+    }
+    let mut mem_size = None;
+    for (op, value) in insn.ops.iter().zip(values){
+        if let Some(s) = match_op(*op, value, size)?{
+            mem_size = Some(s);
+        }
+    }
+    Some(mem_size)
+}
+// a positive imm32 which turns negative in a 64bit operand
+pub fn sign_extended(insn: &Insn, size: u8, values: &[Value]) -> Option<u64>{
+    if size != 8{
+        return None;
+    }
+    insn.ops.iter().zip(values).filter(|(op, _)| **op == Imm).find_map(|(_, value)| {
+        figure(value).filter(|v| (0x8000_0000..0x1_0000_0000).contains(v))
+    })
+}
+fn imm_size(op: Op, size: u8) -> usize{
+    match op{
+        Imm8 | Imm8S => 1,
+        Imm => size.clamp(1, 4) as usize,
+        Imm16 => 2,
+        ImmU32 => 4,
+        Imm64 => 8,
+        _ => 0,
+    }
+}
+pub fn encode<'a>(insn: &Insn, size: u8, values: &[Value<'a>])
+    -> Result<(Vec<u8>, Option<Relocation<'a>>), Error>{
+    // prefixes are chosen by size
+    let prefix_size = if insn.d64 && size == 8 {0} else {size};
+    let operand = |f: fn(&Op) -> bool| insn.ops.iter().zip(values).find(|(op, _)| f(op)).map(|(_, v)| v);
+    let reg = operand(|op| matches!(op, R(_)));
+    // imul reg, imm is imul reg, reg, imm
+    let rm = operand(|op| matches!(op, Rm(_) | M)).or(reg);
+    let (mut data, mut reloc) = match (insn.modrm, reg, rm){
+        (ModRm::R, Some(&Value::Reg(reg, reg_size)), Some(rm)) =>{
+            encode_rm(&insn.opcode, prefix_size, RegField::Reg(reg, reg_size), rm)?
+        },
+        (ModRm::Digit(digit), _, Some(rm)) =>{
+            encode_rm(&insn.opcode, prefix_size, RegField::Digit(digit), rm)?
+        },
+        (ModRm::PlusR, Some(&Value::Reg(r::RAX, 4)), _) if insn.opcode == [0x90] =>{
+            return Err((Code::NoEncoding, "xchg eax, eax is encoded as 87 /r."));
+        },
+        (ModRm::PlusR, Some(&Value::Reg(reg, _)), _) =>{
+            (encode_plus_r(&insn.opcode, prefix_size, reg)?, None)
+        },
+        _ => (encode_op(&insn.opcode, prefix_size), None),
+    };
+    for (op, value) in insn.ops.iter().zip(values){
+        let n = imm_size(*op, size);
+        if n == 0{
+            continue;
+        }
+        match value{
+            Value::Imm(Expr{value, label: Some(label)}) =>{
+                if reloc.is_some(){
+                    return Err((Code::TooManyRelocations, "Too many relocations."));
+                }
+                let kind = if n == 8 {RelocKind::Addr64} else {RelocKind::Addr32};
+                reloc = Some(Relocation::new(data.len(), label, kind));
+                // the addend in place
+                data.extend(&value.to_le_bytes()[..n]);
+            },
+            _ => data.extend(&figure(value).unwrap_or(0).to_le_bytes()[..n]),
+        }
+    }
+    Ok((data, reloc))
+}
+#[cfg(test)]
+mod tests{
+    use super::super::Asm;
+    // bytes of the lines in .text, through the whole assembler
+    fn assemble(lines: &str) -> Result<Vec<u8>, String>{
+        let source = format!("section .text\n{}\n", lines);
+        let asm = Asm{m_contents: &source, ..Default::default()};
+        asm.start().map_err(|errors| errors[0].message.clone())?;
+        let data = asm.sections.borrow()[0].data.clone();
+        Ok(data)
+    }
+    fn check(cases: &[(&str, &[u8])]){
+        for (line, bytes) in cases{
+            assert_eq!(assemble(line), Ok(bytes.to_vec()), "{}", line);
+        }
+    }
+    #[test]
+    fn alu(){
+        check(&[
+            ("add eax, 1", &[0x83, 0xC0, 0x01]),
+            ("add eax, 0x100", &[0x05, 0x00, 0x01, 0x00, 0x00]),
+            ("add ecx, 0x100", &[0x81, 0xC1, 0x00, 0x01, 0x00, 0x00]),
+            ("add al, 1", &[0x04, 0x01]),
+            ("add rax, -1", &[0x48, 0x83, 0xC0, 0xFF]),
+            ("cmp rax, 0x12345678", &[0x48, 0x3D, 0x78, 0x56, 0x34, 0x12]),
+            ("sub rbx, 0x80", &[0x48, 0x81, 0xEB, 0x80, 0x00, 0x00, 0x00]),
+            ("xor r9, rax", &[0x49, 0x31, 0xC1]),
+            ("add qword [rax], 1", &[0x48, 0x83, 0x00, 0x01]),
+            ("test ecx, 0xFF", &[0xF7, 0xC1, 0xFF, 0x00, 0x00, 0x00]),
+        ]);
+    }
+    #[test]
+    fn plus_r(){
+        check(&[
+            ("push rbx", &[0x53]),
+            ("push r12", &[0x41, 0x54]),
+            ("pop r15", &[0x41, 0x5F]),
+            ("mov ecx, 1", &[0xB9, 0x01, 0x00, 0x00, 0x00]),
+            ("mov r8d, 1", &[0x41, 0xB8, 0x01, 0x00, 0x00, 0x00]),
+            ("mov rax, 1", &[0xB8, 0x01, 0x00, 0x00, 0x00]),
+            ("mov rax, 0x123456789", &[0x48, 0xB8, 0x89, 0x67, 0x45, 0x23, 0x01, 0x00, 0x00, 0x00]),
+            ("mov r10b, 5", &[0x41, 0xB2, 0x05]),
+            ("xchg rax, rcx", &[0x48, 0x91]),
+        ]);
+    }
+    #[test]
+    fn sib(){
+        check(&[
+            ("mov eax, [rsp]", &[0x8B, 0x04, 0x24]),
+            ("mov eax, [r12]", &[0x41, 0x8B, 0x04, 0x24]),
+            ("mov eax, [rbp]", &[0x8B, 0x45, 0x00]),
+            ("mov eax, [r13]", &[0x41, 0x8B, 0x45, 0x00]),
+            ("mov eax, [rax+rcx*4]", &[0x8B, 0x04, 0x88]),
+            ("mov eax, [rcx*4]", &[0x8B, 0x04, 0x8D, 0x00, 0x00, 0x00, 0x00]),
+            ("mov eax, [rsp+8]", &[0x8B, 0x44, 0x24, 0x08]),
+            ("mov eax, [rbp+rax]", &[0x8B, 0x44, 0x05, 0x00]),
+        ]);
+    }
+    #[test]
+    fn rex_byte_registers(){
+        check(&[
+            ("mov spl, 1", &[0x40, 0xB4, 0x01]),
+            ("mov al, sil", &[0x40, 0x88, 0xF0]),
+            ("add dil, 1", &[0x40, 0x80, 0xC7, 0x01]),
+            ("mov al, bl", &[0x88, 0xD8]),
+            ("mov ah, al", &[0x88, 0xC4]),
+        ]);
+        for line in ["mov ah, sil", "mov ah, r8b", "add bh, dil"]{
+            assert_eq!(assemble(line), Err("Can't use ah, ch, dh or bh with REX prefix.".to_string()), "{}", line);
+        }
+    }
+    #[test]
+    fn shift(){
+        check(&[
+            ("shl eax, 1", &[0xD1, 0xE0]),
+            ("shr rax, cl", &[0x48, 0xD3, 0xE8]),
+            ("sar ecx, 4", &[0xC1, 0xF9, 0x04]),
+            ("rol byte [rax], 1", &[0xD0, 0x00]),
+            ("rcr r8w, 3", &[0x66, 0x41, 0xC1, 0xD8, 0x03]),
+        ]);
+    }
+    #[test]
+    fn unary(){
+        check(&[
+            ("mul rcx", &[0x48, 0xF7, 0xE1]),
+            ("div ebx", &[0xF7, 0xF3]),
+            ("idiv qword [rsp]", &[0x48, 0xF7, 0x3C, 0x24]),
+            ("neg al", &[0xF6, 0xD8]),
+            ("not r9", &[0x49, 0xF7, 0xD1]),
+            ("inc dword [rbx]", &[0xFF, 0x03]),
+            ("imul rax, rbx", &[0x48, 0x0F, 0xAF, 0xC3]),
+            ("imul eax, ecx, 10", &[0x6B, 0xC1, 0x0A]),
+            ("imul rax, 1000", &[0x48, 0x69, 0xC0, 0xE8, 0x03, 0x00, 0x00]),
+        ]);
+    }
+    #[test]
+    fn extend(){
+        check(&[
+            ("movzx eax, byte [rdi]", &[0x0F, 0xB6, 0x07]),
+            ("movzx rax, cx", &[0x48, 0x0F, 0xB7, 0xC1]),
+            ("movsx ecx, al", &[0x0F, 0xBE, 0xC8]),
+            ("movsx r8, word [rax]", &[0x4C, 0x0F, 0xBF, 0x00]),
+            ("movsxd rax, ecx", &[0x48, 0x63, 0xC1]),
+        ]);
+    }
+    #[test]
+    fn conditional(){
+        check(&[
+            ("cmovz eax, ecx", &[0x0F, 0x44, 0xC1]),
+            ("cmovge rax, [rbx]", &[0x48, 0x0F, 0x4D, 0x03]),
+            ("setne al", &[0x0F, 0x95, 0xC0]),
+            ("setl sil", &[0x40, 0x0F, 0x9C, 0xC6]),
+        ]);
+    }
+    #[test]
+    fn nop(){
+        check(&[
+            ("nop", &[0x90]),
+            ("nop eax", &[0x0F, 0x1F, 0xC0]),
+            ("times 3 nop", &[0x90, 0x90, 0x90]),
+            ("ret\nalign 4, nop", &[0xC3, 0x90, 0x90, 0x90]),
+        ]);
+        assert_eq!(assemble("times 2 push rax rbx"), Err("Syntax Error.".to_string()));
+    }
+    #[test]
+    fn no_operands(){
+        check(&[
+            ("leave", &[0xC9]),
+            ("syscall", &[0x0F, 0x05]),
+            ("cqo", &[0x48, 0x99]),
+            ("cdq", &[0x99]),
+            ("cwd", &[0x66, 0x99]),
+            ("cdqe", &[0x48, 0x98]),
+            ("pause", &[0xF3, 0x90]),
+            ("movsb", &[0xA4]),
+            ("stosq", &[0x48, 0xAB]),
+            ("rep movsb", &[0xF3, 0xA4]),
+            ("repne scasb", &[0xF2, 0xAE]),
+            ("lock add [rax], eax", &[0xF0, 0x01, 0x00]),
+            ("int 0x80", &[0xCD, 0x80]),
+            ("enter 16, 0", &[0xC8, 0x10, 0x00, 0x00]),
+        ]);
+        // never a label without a colon
+        for line in ["bswap eax", "lahf"]{
+            assert_eq!(assemble(line), Err("Instruction is not supported.".to_string()), "{}", line);
+        }
+    }
+    #[test]
+    fn lea_and_push(){
+        check(&[
+            ("lea rax, [rbx+rcx*8+16]", &[0x48, 0x8D, 0x44, 0xCB, 0x10]),
+            ("lea eax, [rax+rax*2]", &[0x8D, 0x04, 0x40]),
+            ("push 1", &[0x6A, 0x01]),
+            ("push -1", &[0x6A, 0xFF]),
+            ("push 0x1000", &[0x68, 0x00, 0x10, 0x00, 0x00]),
+        ]);
+    }
+}
@@ -0,0 +1,73 @@
+use std::io::Write;
+use super::{AsmError, Section, SectionKind};
+use crate::preproc::LineMap;
+use crate::diagnostic::{Code, Diagnostic};
+use super::coff::Coff;
+use super::elf::Elf64;
+use super::bin::Bin;
+#[derive(Clone, Copy, PartialEq)]
+pub enum Bind{
+    // labels not listed in global
+    Local,
+    Global,
+    // undefined, section 0
+    Extern,
+    // tentative definition with the alignment, value is the size
+    Common(u64),
+}
+// symbol of the module. section is 1 based
+pub struct Symbol<'a>{
+    pub name: & 'a str,
+    pub section: usize,
+    pub value: u64,
+    pub bind: Bind,
+}
+// what a relocation refers to
+#[derive(Clone, Copy)]
+pub enum Target{
+    // index of the section, relocated by the section symbol ($, $$)
+    Section(usize),
+    // index of the symbol
+    Symbol(usize),
+}
+// assembled sections, symbols and relocations
+pub struct Module<'a, 'b>{
+    pub file: & 'a str,
+    pub contents: & 'a str,
+    pub map: Option<& 'a LineMap>,
+    pub sections: & 'b [Section<'a>],
+    pub symbols: Vec<Symbol<'a>>,
+    // base address of the flat binary
+    pub org: u64,
+}
+impl<'a> Module<'a, '_>{
+    pub fn symbol(&self, name: &str) -> Option<usize>{
+        self.symbols.iter().position(|s| s.name == name)
+    }
+    // symbols first, then section names
+    pub fn target(&self, name: &str) -> Option<Target>{
+        self.symbol(name).map(Target::Symbol)
+            .or_else(|| self.sections.iter().position(|s| s.name == name).map(Target::Section))
+    }
+    pub fn error(&self, word: &str, code: Code, message: &str) -> Diagnostic{
+        let ae = AsmError::new(self.contents, self.map);
+        ae.diagnostic(word, code, message)
+    }
+}
+// output format serialising a module
+pub trait ObjectFormat{
+    // extension of the input file replaced without -o
+    fn extension(&self) -> & 'static str;
+    // kind and alignment of sections with unknown names
+    fn other_section(&self) -> (SectionKind, u64);
+    fn write(&self, module: &Module, out: &mut dyn Write) -> Result<(), Diagnostic>;
+}
+// -f name
+pub fn format(name: &str) -> Option<Box<dyn ObjectFormat>>{
+    match name{
+        "win64" | "coff" => Some(Box::new(Coff)),
+        "elf64" | "elf" => Some(Box::new(Elf64)),
+        "bin" => Some(Box::new(Bin)),
+        _ => None,
+    }
+}
@@ -16,43 +16,61 @@ pub const R12 :u8 = 0b1100;
 pub const R13 :u8 = 0b1101;
 pub const R14 :u8 = 0b1110;
 pub const R15 :u8 = 0b1111;
+// ah, ch, dh, bh
+pub const HIGH:u8 = 0b1_0000;
 
 pub const REX_W:u8 = 3;
 pub const REX_R:u8 = 2;
 pub const REX_X:u8 = 1;
 pub const REX_B:u8 = 0;
+use super::addr::Mem;
+use super::expr::Expr;
 pub enum Value<'a>{
-    Figure(& 'a str),
+    // figure or label
+    Imm(Expr<'a>),
     Reg(u8, u8),// modr/m
+    Mem(Mem<'a>),
 }
 pub fn create_modrm(modf: u8, reg: u8, rm: u8) -> u8{
     modf << 6 | reg << 3| rm
 }
 pub fn create_rex(w: u8, r: u8, x: u8, b: u8) -> u8{
-    0x40 | w << 3 | r << 2 | x << 1 | b<< 0
+    0x40 | w << REX_W | r << REX_R | x << REX_X | b << REX_B
 }
-pub fn reg<'a>(rm: & 'a str) -> Result<Value<'a>, &str>{
+const REGS: [u8; 16] = [RAX, RCX, RDX, RBX, RSP, RBP, RSI, RDI,
+    R8, R9, R10, R11, R12, R13, R14, R15];
+const REG64: [&str; 16] = ["rax", "rcx", "rdx", "rbx", "rsp", "rbp", "rsi", "rdi",
+    "r8", "r9", "r10", "r11", "r12", "r13", "r14", "r15"];
+const REG32: [&str; 16] = ["eax", "ecx", "edx", "ebx", "esp", "ebp", "esi", "edi",
+    "r8d", "r9d", "r10d", "r11d", "r12d", "r13d", "r14d", "r15d"];
+const REG16: [&str; 16] = ["ax", "cx", "dx", "bx", "sp", "bp", "si", "di",
+    "r8w", "r9w", "r10w", "r11w", "r12w", "r13w", "r14w", "r15w"];
+const REG8: [&str; 16] = ["al", "cl", "dl", "bl", "spl", "bpl", "sil", "dil",
+    "r8b", "r9b", "r10b", "r11b", "r12b", "r13b", "r14b", "r15b"];
+const REG8H: [&str; 4] = ["ah", "ch", "dh", "bh"];
+pub fn reg<'a>(rm: & 'a str) -> Result<Value<'a>, &'a str>{
     let _r = rm.to_lowercase();
+    let find = |names: &[&str]| names.iter().position(|n| *n == _r).map(|i| REGS[i]);
 
-    match _r.as_str(){
-        "rax" => Ok(Value::Reg(RAX, 8)),
-        "rcx" => Ok(Value::Reg(RCX, 8)),
-        "rdx" => Ok(Value::Reg(RDX, 8)),
-        "rbx" => Ok(Value::Reg(RBX, 8)),
-        "rsp" => Ok(Value::Reg(RSP, 8)),
-        "rbp" => Ok(Value::Reg(RBP, 8)),
-        "rsi" => Ok(Value::Reg(RSI, 8)),
-        "rdi" => Ok(Value::Reg(RDI, 8)),
-        "r8" => Ok(Value::Reg(R8, 8)),
-        "r9" => Ok(Value::Reg(R9, 8)),
-        "r10" => Ok(Value::Reg(R10, 8)),
-        "r11" => Ok(Value::Reg(R11, 8)),
-        "r12" => Ok(Value::Reg(R12, 8)),
-        "r13" => Ok(Value::Reg(R13, 8)),
-        "r14" => Ok(Value::Reg(R14, 8)),
-        "r15" => Ok(Value::Reg(R15, 8)),
-        _ => {
-            return Err(rm);
-        },
+    if let Some(reg) = find(&REG64){
+        Ok(Value::Reg(reg, 8))
+    }else if let Some(reg) = find(&REG32){
+        Ok(Value::Reg(reg, 4))
+    }else if let Some(reg) = find(&REG16){
+        Ok(Value::Reg(reg, 2))
+    }else if let Some(reg) = find(&REG8){
+        Ok(Value::Reg(reg, 1))
+    }else if let Some(reg) = find(&REG8H){
+        // ah = spl + HIGH
+        Ok(Value::Reg(HIGH | (reg + RSP), 1))
+    }else{
+        Err(rm)
     }
 }
+// spl, bpl, sil, dil
+pub fn needs_rex(reg: u8, size: u8) -> bool{
+    size == 1 && (RSP..=RDI).contains(&reg)
+}
+pub fn is_high(reg: u8) -> bool{
+    reg & HIGH != 0
+}
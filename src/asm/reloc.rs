@@ -0,0 +1,82 @@
+use super::headers::*;
+use super::elf::*;
+#[derive(Clone, Copy, PartialEq)]
+pub enum RelocKind{
+    Addr64,
+    Addr32,
+    // absolute disp32, sign extended to 64 bits
+    Addr32S,
+    Addr16,
+    // image base relative (..imagebase)
+    Addr32Nb,
+    // rip relative, with the number of bytes after the field
+    Rel32(u8),
+    // call or jmp through the plt (wrt ..plt)
+    Plt32,
+    // rip relative got entry (wrt ..gotpcrel)
+    GotPcRel(u8),
+    // section index (seg)
+    Section,
+    // section relative (..secrel)
+    SecRel,
+}
+impl RelocKind{
+    pub fn size(&self) -> usize{
+        match self{
+            RelocKind::Addr64 => 8,
+            RelocKind::Addr16 | RelocKind::Section => 2,
+            _ => 4,
+        }
+    }
+    pub fn coff_type(&self) -> Option<u16>{
+        match self{
+            RelocKind::Addr64 => Some(IMAGE_REL_AMD64_ADDR64),
+            RelocKind::Addr32 | RelocKind::Addr32S => Some(IMAGE_REL_AMD64_ADDR32),
+            RelocKind::Addr32Nb => Some(IMAGE_REL_AMD64_ADDR32NB),
+            // REL32_1 .. REL32_5
+            RelocKind::Rel32(n) => Some(IMAGE_REL_AMD64_REL32 + *n as u16),
+            RelocKind::Section => Some(IMAGE_REL_AMD64_SECTION),
+            RelocKind::SecRel => Some(IMAGE_REL_AMD64_SECREL),
+            RelocKind::Addr16 | RelocKind::Plt32 | RelocKind::GotPcRel(_) => None,
+        }
+    }
+    pub fn elf_type(&self) -> Option<u32>{
+        match self{
+            RelocKind::Addr64 => Some(R_X86_64_64),
+            RelocKind::Addr32 => Some(R_X86_64_32),
+            RelocKind::Addr32S => Some(R_X86_64_32S),
+            RelocKind::Addr16 => Some(R_X86_64_16),
+            RelocKind::Rel32(_) => Some(R_X86_64_PC32),
+            RelocKind::Plt32 => Some(R_X86_64_PLT32),
+            RelocKind::GotPcRel(_) => Some(R_X86_64_GOTPCREL),
+            RelocKind::Addr32Nb | RelocKind::Section | RelocKind::SecRel => None,
+        }
+    }
+    // rip relative kinds count from the end of the instruction
+    pub fn pc_bias(&self) -> i64{
+        match self{
+            RelocKind::Rel32(n) | RelocKind::GotPcRel(n) => 4 + *n as i64,
+            RelocKind::Plt32 => 4,
+            _ => 0,
+        }
+    }
+}
+// the addend is stored in place
+pub struct Relocation<'a>{
+    pub offset: usize,
+    pub symbol: & 'a str,
+    pub kind: RelocKind,
+}
+impl<'a> Relocation<'a>{
+    pub fn new(offset: usize, symbol: & 'a str, kind: RelocKind) -> Self{
+        Self{offset, symbol, kind}
+    }
+    // the addend stored in the section data
+    pub fn addend(&self, data: &[u8]) -> i64{
+        let size = self.kind.size();
+        let mut field = [0u8; 8];
+        field[..size].copy_from_slice(&data[self.offset..self.offset + size]);
+        let shift = 64 - size as u32 * 8;
+        (i64::from_le_bytes(field) << shift) >> shift
+    }
+}
@@ -0,0 +1,283 @@
+// errors and warnings with their place in the source.
+// an error gives up the line, it goes back as Err to the loop over the lines
+use std::fmt;
+use std::io;
+// error codes, the hundreds are the groups
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Code{
+    // syntax
+    Syntax = 101,
+    Colon = 102,
+    UnknownDirective = 103,
+    InvalidFigure = 104,
+    TooLargeFigure = 105,
+    UnterminatedString = 106,
+    InvalidEscape = 107,
+    InvalidCharacter = 108,
+    InvalidUtf8 = 109,
+    // something is missing
+    RequireFigure = 110,
+    RequireLabel = 111,
+    RequireSymbol = 112,
+    RequireSection = 113,
+    RequireName = 114,
+    RequireInstruction = 116,
+    RequireComma = 117,
+    RequireBracket = 118,
+    RequireParen = 119,
+    RequireWrt = 120,
+    RequireSpecial = 121,
+    RequireMode = 122,
+    RequireFloat = 123,
+    RequireOperand = 124,
+    RequireData = 125,
+    RequireRegister = 126,
+    RequireParameters = 127,
+    // symbols
+    LabelRedefined = 201,
+    SymbolRedefined = 202,
+    UndefinedSymbol = 203,
+    GlobalUndefined = 204,
+    CommonDefined = 205,
+    // expressions
+    NotAbsolute = 301,
+    NotSimple = 302,
+    NotConstant = 303,
+    NotRelocatable = 304,
+    DivisionByZero = 305,
+    CharacterTooLong = 306,
+    NegativeCount = 307,
+    NoConvergence = 308,
+    // operands
+    InvalidOperands = 401,
+    SizeNotSpecified = 402,
+    SubtractRegister = 403,
+    RegisterNotAllowed = 404,
+    TooManyRegisters = 405,
+    InvalidScale = 406,
+    RspIndex = 407,
+    RelWithRegisters = 408,
+    GotNotRelative = 409,
+    LargeDisplacement = 410,
+    HighByteRex = 411,
+    ShortJumpRange = 412,
+    ShortJumpSection = 413,
+    TooManyRelocations = 414,
+    NoEncoding = 415,
+    UnsupportedInstruction = 416,
+    // sections and data
+    NoSection = 501,
+    UnknownSection = 502,
+    InvalidAlignment = 503,
+    NobitsData = 504,
+    StartFollows = 505,
+    SectionBeforeOrg = 506,
+    FloatSize = 507,
+    StringFunction = 508,
+    RelocationSize = 509,
+    SectionOverlap = 510,
+    SectionAlignment = 511,
+    UnknownAttribute = 512,
+    FollowsCycle = 513,
+    LargeExponent = 514,
+    // output and options
+    UnsupportedRelocation = 601,
+    BinExtern = 602,
+    AddressOverflow = 603,
+    CantOpen = 604,
+    CantCreate = 605,
+    CantWrite = 606,
+    UnknownFormat = 607,
+    UnknownOption = 608,
+    UnknownWarning = 609,
+    // preprocessor, %error is 700
+    UserError = 700,
+    MissingEnd = 701,
+    EndmacroWithoutMacro = 702,
+    EndrepWithoutRep = 703,
+    ElseNotLast = 704,
+    TooDeepInclude = 705,
+    WrongParameters = 706,
+    MissingIf = 707,
+    TooDeepMacro = 708,
+}
+impl fmt::Display for Code{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        write!(f, "E{:04}", *self as u16)
+    }
+}
+// an error before it gets its place, the caller knows the word
+pub type Error = (Code, & 'static str);
+// warning classes for -w+name and -w-name, all on by default
+pub const WARNINGS: [&str; 6] = [
+    // data or immediate too large for its size
+    "number-overflow",
+    // a positive figure which becomes negative when sign extended
+    "sign-extend",
+    // a word alone on a line taken as a label
+    "label-orphan",
+    // a label defined twice at the same place
+    "label-redef",
+    // an instruction right after ret or jmp
+    "unreachable",
+    // %warning
+    "user",
+];
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Severity{
+    Error,
+    Warning,
+}
+impl fmt::Display for Severity{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        match self{
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+// a place in the source before preprocessing
+#[derive(Clone, Debug)]
+pub struct Span{
+    pub file: String,
+    pub line: usize,
+    // 1 based, in bytes
+    pub column: usize,
+    pub len: usize,
+    // the whole line
+    pub text: String,
+}
+#[derive(Clone, Debug)]
+pub struct Label{
+    pub span: Span,
+    pub message: String,
+}
+#[derive(Clone, Debug)]
+pub struct Diagnostic{
+    pub severity: Severity,
+    pub code: String,
+    pub message: String,
+    // the primary label comes first
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+}
+impl Diagnostic{
+    pub fn error(code: Code, message: &str) -> Self{
+        Self::new(Severity::Error, &code.to_string(), message)
+    }
+    // the code of a warning is its class
+    pub fn warning(class: & 'static str, message: &str) -> Self{
+        Self::new(Severity::Warning, class, message)
+    }
+    fn new(severity: Severity, code: &str, message: &str) -> Self{
+        Self{severity, code: code.to_string(), message: message.to_string(), labels: Vec::new(), notes: Vec::new()}
+    }
+    // nothing without a span
+    pub fn label(mut self, span: Option<Span>, message: &str) -> Self{
+        if let Some(span) = span{
+            self.labels.push(Label{span, message: message.to_string()});
+        }
+        self
+    }
+    pub fn note(mut self, note: &str) -> Self{
+        self.notes.push(note.to_string());
+        self
+    }
+    pub fn is_error(&self) -> bool{
+        self.severity == Severity::Error
+    }
+}
+// writing the output, ? in the object formats
+impl From<io::Error> for Diagnostic{
+    fn from(e: io::Error) -> Self{
+        Diagnostic::error(Code::CantWrite, &format!("Can't write the output file: {}.", e))
+    }
+}
+// error[E0201]: Label redefined.
+//  --> a.asm:5:1
+//   |
+// 5 | foo:
+//   | ^^^
+impl fmt::Display for Diagnostic{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        match self.severity{
+            Severity::Warning => writeln!(f, "{}: {} [-w+{}]", self.severity, self.message, self.code)?,
+            Severity::Error => writeln!(f, "{}[{}]: {}", self.severity, self.code, self.message)?,
+        }
+        let width = self.labels.iter().map(|l| l.span.line.to_string().len()).max().unwrap_or(0);
+        let gutter = " ".repeat(width);
+        for (i, label) in self.labels.iter().enumerate(){
+            let span = &label.span;
+            let (arrow, mark) = if i == 0 {("-->", "^")} else {(":::", "-")};
+            writeln!(f, "{} {} {}:{}:{}", gutter, arrow, span.file, span.line, span.column)?;
+            writeln!(f, "{} |", gutter)?;
+            writeln!(f, "{:>width$} | {}", span.line, span.text)?;
+            // tabs keep the marks under the word
+            let before = span.text.get(..span.column.saturating_sub(1)).unwrap_or("");
+            let pad: String = before.chars().map(|c| if c == '\t' {'\t'} else {' '}).collect();
+            let message = if label.message.is_empty() {String::new()} else {format!(" {}", label.message)};
+            writeln!(f, "{} | {}{}{}", gutter, pad, mark.repeat(span.len.max(1)), message)?;
+        }
+        for note in &self.notes{
+            writeln!(f, "{} = note: {}", gutter, note)?;
+        }
+        Ok(())
+    }
+}
+// 3 errors, 1 warning
+pub fn summary(diagnostics: &[Diagnostic]) -> String{
+    let errors = diagnostics.iter().filter(|d| d.is_error()).count();
+    let warnings = diagnostics.len() - errors;
+    let plural = |n: usize, word: &str| format!("{} {}{}", n, word, if n == 1 {""} else {"s"});
+    match (errors, warnings){
+        (_, 0) => plural(errors, "error"),
+        (0, _) => plural(warnings, "warning"),
+        _ => format!("{}, {}", plural(errors, "error"), plural(warnings, "warning")),
+    }
+}
+// -w+name, -w-name and -Werror
+#[derive(Default)]
+pub struct WarningFlags{
+    disabled: Vec<& 'static str>,
+    error: bool,
+}
+impl WarningFlags{
+    // all is every class
+    pub fn parse(&mut self, arg: &str) -> Result<(), Diagnostic>{
+        if arg == "-Werror" || arg == "-w+error"{
+            self.error = true;
+            return Ok(());
+        }
+        if arg == "-w-error"{
+            self.error = false;
+            return Ok(());
+        }
+        let (enable, name) = match (arg.strip_prefix("-w+"), arg.strip_prefix("-w-")){
+            (Some(name), _) => (true, name),
+            (_, Some(name)) => (false, name),
+            _ => return Err(Diagnostic::error(Code::UnknownOption, &format!("Unknown option: {}.", arg))),
+        };
+        let classes: Vec<& 'static str> = match name{
+            "all" => WARNINGS.to_vec(),
+            _ => WARNINGS.iter().copied().filter(|w| *w == name).collect(),
+        };
+        if classes.is_empty(){
+            let note = format!("Warnings are all, error, {}.", WARNINGS.join(", "));
+            return Err(Diagnostic::error(Code::UnknownWarning, &format!("Unknown warning: {}.", name)).note(&note));
+        }
+        self.disabled.retain(|w| !classes.contains(w));
+        if !enable{
+            self.disabled.extend(classes);
+        }
+        Ok(())
+    }
+    // drops disabled warnings, -Werror turns the rest into errors
+    pub fn apply(&self, diagnostics: Vec<Diagnostic>) -> Vec<Diagnostic>{
+        diagnostics.into_iter().filter(|d| d.is_error() || !self.disabled.contains(&d.code.as_str())).map(|mut d| {
+            if self.error{
+                d.severity = Severity::Error;
+            }
+            d
+        }).collect()
+    }
+}
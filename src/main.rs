@@ -1,23 +1,90 @@
 mod asm;
 use asm::Asm;
+mod preproc;
+mod diagnostic;
+use diagnostic::{Code, Diagnostic, WarningFlags};
 
 use std::env;
-use std::fs::File;
-use std::io::prelude::*;
+use std::fs;
+use std::process;
+use std::path::{Path, PathBuf};
+// punas [-f win64|elf64|bin] [-o output] [-I path] [-w+name|-w-name|-Werror] file
 fn main(){
     let args: Vec<String> = env::args().collect();
-    if args.len() < 2 { 
+    let mut format = "win64";
+    let mut output = None;
+    let mut filename = None;
+    // %include search paths
+    let mut include = Vec::new();
+    let mut warnings = WarningFlags::default();
+    let mut errors = Vec::new();
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next(){
+        match arg.as_str(){
+            "-f" => format = iter.next().map_or("", |s| s.as_str()),
+            "-o" => output = iter.next(),
+            "-I" => include.extend(iter.next().cloned()),
+            _ if arg.starts_with("-I") => include.push(arg[2..].to_string()),
+            _ if arg.starts_with("-w") || arg.starts_with("-W") =>{
+                if let Err(error) = warnings.parse(arg){
+                    errors.push(error);
+                }
+            },
+            _ => filename = Some(arg),
+        }
+    }
+    let Some(filename) = filename else{
         return;
+    };
+    let diagnostics = if !errors.is_empty() {errors} else{
+        match run(filename, format, output, &include, &warnings){
+            Ok(diagnostics) => diagnostics,
+            Err(diagnostics) => diagnostics,
+        }
+    };
+    for diagnostic in &diagnostics{
+        eprintln!("{}", diagnostic);
+    }
+    if diagnostics.iter().any(|d| d.is_error()){
+        eprintln!("{}", diagnostic::summary(&diagnostics));
+        process::exit(1);
+    }
+}
+// warnings, or everything when it failed
+fn run(filename: &str, format: &str, output: Option<&String>, include: &[String], warnings: &WarningFlags)
+    -> Result<Vec<Diagnostic>, Vec<Diagnostic>>{
+    let Some(format) = asm::format(format) else{
+        return Err(vec![Diagnostic::error(Code::UnknownFormat, &format!("Unknown output format: {}.", format))]);
+    };
+    let contents = fs::read_to_string(filename).map_err(|e| vec![Diagnostic::error(Code::CantOpen, &format!("Can't open {}: {}.", filename, e))])?;
+    let source = preproc::preprocess(filename, &contents, include);
+    let mut diagnostics = warnings.apply(source.diagnostics);
+    if diagnostics.iter().any(|d| d.is_error()){
+        return Err(diagnostics);
     }
+    let input = source.text.as_str();
 
-    let filename = &args[1];
-    let mut f = File::open(filename).expect("file not found");
-    let mut contents = String::new();
-    f.read_to_string(&mut contents).expect("something went wrong reading the file");
-    let input = contents.as_str();
-    
-    let asm = Asm::new(filename, input);
-    asm.start();
-    asm.debug();
-    asm.write("test.obj");
+    let asm = Asm::new(filename, input, &source.map, include, format.as_ref());
+    let data = asm.start().and_then(|()| asm.write(format.as_ref()));
+    diagnostics.extend(warnings.apply(asm.warnings()));
+    let data = match data{
+        // -Werror
+        Ok(_) if diagnostics.iter().any(|d| d.is_error()) => return Err(diagnostics),
+        Ok(data) => data,
+        Err(errors) =>{
+            diagnostics.extend(errors);
+            return Err(diagnostics);
+        },
+    };
+    // foo.asm is assembled to foo.obj, foo.o or foo
+    let mut default = Path::new(filename).with_extension(format.extension());
+    if default == Path::new(filename){
+        default = PathBuf::from("punas.out");
+    }
+    let output = output.map_or(default, PathBuf::from);
+    if let Err(e) = fs::write(&output, data){
+        diagnostics.push(Diagnostic::error(Code::CantCreate, &format!("Can't create {}: {}.", output.display(), e)));
+        return Err(diagnostics);
+    }
+    Ok(diagnostics)
 }
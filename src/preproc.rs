@@ -0,0 +1,494 @@
+// %define, %assign, %macro, %rep, %if, %include, %error and %warning.
+// %define names are replaced as text in every other line
+use std::fs;
+use std::path::Path;
+use crate::asm;
+use crate::diagnostic::{Code, Diagnostic, Span};
+// nested includes and macro calls
+const MAX_DEPTH: usize = 64;
+const IFS: [&str; 3] = ["if", "ifdef", "ifndef"];
+// file and line number of each output line
+#[derive(Default)]
+pub struct LineMap{
+    files: Vec<String>,
+    lines: Vec<(usize, usize)>,
+}
+impl LineMap{
+    // line is 0 based
+    pub fn origin(&self, line: usize) -> Option<(&str, usize)>{
+        let (file, line) = *self.lines.get(line)?;
+        Some((&self.files[file], line))
+    }
+}
+pub struct Source{
+    pub text: String,
+    pub map: LineMap,
+    // %warning, %error and broken directives
+    pub diagnostics: Vec<Diagnostic>,
+}
+#[derive(Clone)]
+struct Line{
+    text: String,
+    file: usize,
+    line: usize,
+}
+// %macro name min[-max] defaults
+struct Macro{
+    name: String,
+    min: usize,
+    max: usize,
+    defaults: Vec<String>,
+    body: Vec<Line>,
+}
+struct Preprocessor<'a>{
+    // -I
+    include: & 'a [String],
+    macros: Vec<Macro>,
+    // %define and %assign seen so far, the last one wins
+    defines: Vec<(String, String)>,
+    // number of the macro call, for %%labels
+    unique: usize,
+    depth: usize,
+    text: String,
+    map: LineMap,
+    diagnostics: Vec<Diagnostic>,
+}
+pub fn preprocess(file: &str, contents: &str, include: &[String]) -> Source{
+    let mut pp = Preprocessor{
+        include,
+        macros: Vec::new(),
+        defines: Vec::new(),
+        unique: 0,
+        depth: 0,
+        text: String::new(),
+        map: LineMap::default(),
+        diagnostics: Vec::new(),
+    };
+    let lines = pp.load(file, contents);
+    pp.process(&lines);
+    Source{text: pp.text, map: pp.map, diagnostics: pp.diagnostics}
+}
+impl Preprocessor<'_>{
+    fn load(&mut self, file: &str, contents: &str) -> Vec<Line>{
+        let idx = self.map.files.len();
+        self.map.files.push(file.to_string());
+        contents.lines().enumerate().map(|(i, text)| Line{text: text.to_string(), file: idx, line: i + 1}).collect()
+    }
+    fn emit(&mut self, line: &Line){
+        self.text.push_str(&line.text);
+        self.text.push('\n');
+        self.map.lines.push((line.file, line.line));
+    }
+    // the whole line without indentation
+    fn span(&self, line: &Line) -> Span{
+        let code = line.text.trim();
+        let column = line.text.len() - line.text.trim_start().len() + 1;
+        Span{file: self.map.files[line.file].clone(), line: line.line, column, len: code.len(), text: line.text.clone()}
+    }
+    fn error(&self, line: &Line, code: Code, message: &str) -> Diagnostic{
+        Diagnostic::error(code, message).label(Some(self.span(line)), "")
+    }
+    fn warning(&mut self, line: &Line, message: &str){
+        let warning = Diagnostic::warning("user", message).label(Some(self.span(line)), "");
+        self.diagnostics.push(warning);
+    }
+    fn process(&mut self, lines: &[Line]){
+        let mut i = 0;
+        while i < lines.len(){
+            // an error gives up the line
+            match self.line(lines, i){
+                Ok(end) => i = end,
+                Err(diagnostic) => self.diagnostics.push(diagnostic),
+            }
+            i += 1;
+        }
+    }
+    // index of the last line used
+    fn line(&mut self, lines: &[Line], i: usize) -> Result<usize, Diagnostic>{
+        let line = &lines[i];
+        let code = strip_comment(&line.text).trim();
+        let Some((directive, args)) = directive(code) else{
+            let line = Line{text: self.expand_defines(&line.text), ..line.clone()};
+            if !self.invoke(&line, strip_comment(&line.text).trim())?{
+                self.emit(&line);
+            }
+            return Ok(i);
+        };
+        match directive.as_str(){
+            "macro" =>{
+                let end = self.block_end(lines, i, &["macro"], "endmacro")?;
+                // a bad %macro line still skips the body
+                if let Err(diagnostic) = self.define_macro(line, args, &lines[i + 1..end]){
+                    self.diagnostics.push(diagnostic);
+                }
+                return Ok(end);
+            },
+            "rep" =>{
+                let end = self.block_end(lines, i, &["rep"], "endrep")?;
+                let count = self.evaluate(line, args);
+                if count < 0{
+                    self.diagnostics.push(Diagnostic::error(Code::NegativeCount, "Negative count.").label(Some(self.span(line)), ""));
+                }
+                for _ in 0..count{
+                    self.process(&lines[i + 1..end]);
+                }
+                return Ok(end);
+            },
+            d if IFS.contains(&d) =>{
+                return self.conditional(lines, i);
+            },
+            "include" =>{
+                self.include(line, args)?;
+            },
+            "error" => return Err(Diagnostic::error(Code::UserError, unquote(args)).label(Some(self.span(line)), "")),
+            "warning" => self.warning(line, unquote(args)),
+            "define" | "undef" | "assign" =>{
+                self.define(line, &directive, args)?;
+            },
+            "endmacro" => return Err(self.error(line, Code::EndmacroWithoutMacro, "%endmacro without %macro.")),
+            "endrep" => return Err(self.error(line, Code::EndrepWithoutRep, "%endrep without %rep.")),
+            "endif" | "else" | "elif" | "elifdef" | "elifndef" => return Err(self.error(line, Code::MissingIf, "Missing %if.")),
+            _ => return Err(self.error(line, Code::UnknownDirective, "Unknown directive.")),
+        }
+        Ok(i)
+    }
+    // index of the line closing the block at start
+    fn block_end(&self, lines: &[Line], start: usize, opens: &[&str], close: &str) -> Result<usize, Diagnostic>{
+        let mut depth = 0;
+        for (i, line) in lines.iter().enumerate().skip(start + 1){
+            let Some((directive, _)) = directive(strip_comment(&line.text).trim()) else{
+                continue;
+            };
+            if opens.contains(&directive.as_str()){
+                depth += 1;
+            }else if directive == close{
+                if depth == 0{
+                    return Ok(i);
+                }
+                depth -= 1;
+            }
+        }
+        Err(self.error(&lines[start], Code::MissingEnd, &format!("Missing %{}.", close)))
+    }
+    // %if .. %elif .. %else .. %endif, returns the index of %endif
+    fn conditional(&mut self, lines: &[Line], start: usize) -> Result<usize, Diagnostic>{
+        let end = self.block_end(lines, start, &IFS, "endif")?;
+        // %if, %elif and %else at this level
+        let mut heads = vec![start];
+        let mut depth = 0;
+        for (i, line) in lines.iter().enumerate().take(end).skip(start + 1){
+            let Some((directive, _)) = directive(strip_comment(&line.text).trim()) else{
+                continue;
+            };
+            match directive.as_str(){
+                d if IFS.contains(&d) => depth += 1,
+                "endif" => depth -= 1,
+                "else" | "elif" | "elifdef" | "elifndef" if depth == 0 => heads.push(i),
+                _ =>{},
+            }
+        }
+        heads.push(end);
+        for k in 0..heads.len() - 1{
+            let line = &lines[heads[k]];
+            let (directive, args) = directive(strip_comment(&line.text).trim()).unwrap();
+            let yes = match directive.trim_start_matches("el"){
+                "if" => self.evaluate(line, args) != 0,
+                "ifdef" => self.is_defined(args),
+                "ifndef" => !self.is_defined(args),
+                _ if k + 2 < heads.len() =>{
+                    let error = Diagnostic::error(Code::ElseNotLast, "%else must be the last branch.").label(Some(self.span(line)), "");
+                    self.diagnostics.push(error);
+                    break;
+                },
+                _ => true,
+            };
+            if yes{
+                self.process(&lines[heads[k] + 1..heads[k + 1]]);
+                break;
+            }
+        }
+        Ok(end)
+    }
+    fn is_defined(&self, name: &str) -> bool{
+        self.defines.iter().any(|(n, _)| n == name)
+    }
+    // %define name body | %undef name | %assign name expr
+    fn define(&mut self, line: &Line, directive: &str, args: &str) -> Result<(), Diagnostic>{
+        let len = word_len(args);
+        if len == 0{
+            return Err(self.error(line, Code::RequireName, "Require Name."));
+        }
+        let (name, body) = (&args[..len], args[len..].trim());
+        let value = match directive{
+            "define" => Some(body.to_string()),
+            // evaluated once, so %assign i i+1 counts
+            "assign" =>{
+                let value = asm::evaluate(&self.expand_defines(body)).map_err(|diagnostic| diagnostic.label(Some(self.span(line)), ""))?;
+                Some(value.to_string())
+            },
+            _ => None,
+        };
+        self.defines.retain(|(n, _)| n != name);
+        if let Some(value) = value{
+            self.defines.push((name.to_string(), value));
+        }
+        Ok(())
+    }
+    // names replaced by their %define as text
+    fn expand_defines(&self, text: &str) -> String{
+        self.expand(text, &[])
+    }
+    // %define a a+1 refers to the label a, a name isn't replaced inside itself
+    fn expand(&self, text: &str, expanding: &[&str]) -> String{
+        replace_words(text, |word| {
+            if expanding.contains(&word) || expanding.len() >= MAX_DEPTH{
+                return None;
+            }
+            let (_, body) = self.defines.iter().rev().find(|(n, _)| n == word)?;
+            let expanding: Vec<&str> = expanding.iter().copied().chain([word]).collect();
+            Some(self.expand(body, &expanding))
+        })
+    }
+    // 0 after an error, so the block is skipped
+    fn evaluate(&mut self, line: &Line, args: &str) -> i64{
+        let text = self.expand_defines(args);
+        asm::evaluate(&text).unwrap_or_else(|diagnostic| {
+            self.diagnostics.push(diagnostic.label(Some(self.span(line)), ""));
+            0
+        })
+    }
+    fn include(&mut self, line: &Line, args: &str) -> Result<(), Diagnostic>{
+        let name = args.trim_matches(|c| c == '"' || c == '\'' || c == '<' || c == '>');
+        let mut paths = vec![Path::new(name).to_path_buf()];
+        paths.extend(self.include.iter().map(|dir| Path::new(dir).join(name)));
+        let Some((path, contents)) = paths.iter().find_map(|path| Some((path, fs::read_to_string(path).ok()?))) else{
+            return Err(self.error(line, Code::CantOpen, &format!("Can't open {}.", name)));
+        };
+        if self.depth >= MAX_DEPTH{
+            return Err(self.error(line, Code::TooDeepInclude, "Too deep %include."));
+        }
+        let lines = self.load(&path.to_string_lossy(), &contents);
+        self.depth += 1;
+        self.process(&lines);
+        self.depth -= 1;
+        Ok(())
+    }
+    // %macro name min[-max|-*] [default, ...]
+    fn define_macro(&mut self, line: &Line, args: &str, body: &[Line]) -> Result<(), Diagnostic>{
+        let mut words = args.splitn(3, char::is_whitespace).filter(|w| !w.is_empty());
+        let Some(name) = words.next() else{
+            return Err(self.error(line, Code::RequireName, "Require Name."));
+        };
+        let count = words.next().unwrap_or("0");
+        let (min, max) = count.split_once('-').unwrap_or((count, count));
+        let Ok(min) = min.parse::<usize>() else{
+            return Err(self.error(line, Code::RequireParameters, "Require the number of parameters."));
+        };
+        let max = if max == "*" {usize::MAX} else{
+            let Ok(max) = max.parse::<usize>() else{
+                return Err(self.error(line, Code::RequireParameters, "Require the number of parameters."));
+            };
+            max
+        };
+        let defaults = words.next().map_or(Vec::new(), split_args);
+        self.macros.push(Macro{name: name.to_string(), min, max, defaults, body: body.to_vec()});
+        Ok(())
+    }
+    // [label:] name args, false if it is not a macro
+    fn invoke(&mut self, line: &Line, code: &str) -> Result<bool, Diagnostic>{
+        let mut code = code;
+        let mut label = None;
+        let len = word_len(code);
+        if code[len..].starts_with(':'){
+            label = Some(&code[..len + 1]);
+            code = code[len + 1..].trim_start();
+        }
+        let len = word_len(code);
+        let (name, args) = (&code[..len], code[len..].trim());
+        if len == 0 || !self.macros.iter().any(|m| m.name == name){
+            return Ok(false);
+        }
+        let mut args = if args.is_empty() {Vec::new()} else {split_args(args)};
+        let Some(m) = self.macros.iter().rev().find(|m| m.name == name && (m.min..=m.max).contains(&args.len())) else{
+            return Err(self.error(line, Code::WrongParameters, &format!("Wrong number of parameters for {}.", name)));
+        };
+        // defaults fill min+1..max
+        let given = args.len();
+        args.extend(m.defaults.iter().skip(given.saturating_sub(m.min)).cloned());
+        self.unique += 1;
+        let body: Vec<Line> = m.body.iter().map(|l| Line{text: substitute(&l.text, &args, given, self.unique), ..l.clone()}).collect();
+        if let Some(label) = label{
+            self.emit(&Line{text: label.to_string(), ..line.clone()});
+        }
+        if self.depth >= MAX_DEPTH{
+            return Err(self.error(line, Code::TooDeepMacro, "Too deep macro call."));
+        }
+        self.depth += 1;
+        self.process(&body);
+        self.depth -= 1;
+        Ok(true)
+    }
+}
+// %name args, lowercase
+fn directive(code: &str) -> Option<(String, &str)>{
+    let s = code.strip_prefix('%')?;
+    let len = s.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(s.len());
+    if len == 0{
+        return None;
+    }
+    Some((s[..len].to_lowercase(), s[len..].trim()))
+}
+fn is_word_char(c: char) -> bool{
+    c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '@'
+}
+fn word_len(s: &str) -> usize{
+    if s.starts_with(|c: char| c.is_ascii_digit()){
+        return 0;
+    }
+    s.find(|c: char| !is_word_char(c)).unwrap_or(s.len())
+}
+// up to ; outside quotes
+fn strip_comment(text: &str) -> &str{
+    let mut quote = None;
+    for (i, c) in text.char_indices(){
+        match quote{
+            Some(q) if c == q => quote = None,
+            Some(_) =>{},
+            None if c == '\'' || c == '"' || c == '`' => quote = Some(c),
+            None if c == ';' => return &text[..i],
+            None =>{},
+        }
+    }
+    text
+}
+fn unquote(s: &str) -> &str{
+    let s = s.trim();
+    for q in ['"', '\'', '`']{
+        if s.len() >= 2 && s.starts_with(q) && s.ends_with(q){
+            return &s[1..s.len() - 1];
+        }
+    }
+    s
+}
+// a, (b, c), {d, e}
+fn split_args(s: &str) -> Vec<String>{
+    let mut args = Vec::new();
+    let mut depth = 0;
+    let mut quote = None;
+    let mut start = 0;
+    for (i, c) in s.char_indices(){
+        match quote{
+            Some(q) if c == q => quote = None,
+            Some(_) =>{},
+            None => match c{
+                '\'' | '"' | '`' => quote = Some(c),
+                '(' | '[' | '{' => depth += 1,
+                ')' | ']' | '}' => depth -= 1,
+                ',' if depth == 0 =>{
+                    args.push(s[start..i].to_string());
+                    start = i + 1;
+                },
+                _ =>{},
+            },
+        }
+    }
+    args.push(s[start..].to_string());
+    args.iter().map(|a| {
+        let a = a.trim();
+        a.strip_prefix('{').and_then(|a| a.strip_suffix('}')).unwrap_or(a).to_string()
+    }).collect()
+}
+// %1 .. %n, %{n}, %0 and %%label outside quotes
+fn substitute(text: &str, args: &[String], given: usize, unique: usize) -> String{
+    let mut out = String::new();
+    let mut quote = None;
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next(){
+        if let Some(q) = quote{
+            if c == q{
+                quote = None;
+            }
+            out.push(c);
+            continue;
+        }
+        if c == '\'' || c == '"' || c == '`'{
+            quote = Some(c);
+        }
+        if c != '%'{
+            out.push(c);
+            continue;
+        }
+        let rest = &text[i + 1..];
+        let local = rest.strip_prefix('%').is_some_and(|s| s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_' || c == '.'));
+        if local{
+            out.push_str(&format!("..@{}.", unique));
+            chars.next();
+            continue;
+        }
+        let braced = rest.starts_with('{');
+        let digits = rest.trim_start_matches('{');
+        let len = digits.find(|c: char| !c.is_ascii_digit()).unwrap_or(digits.len());
+        if len == 0 || (braced && !digits[len..].starts_with('}')){
+            out.push(c);
+            continue;
+        }
+        let n: usize = digits[..len].parse().unwrap_or(0);
+        match n{
+            0 => out.push_str(&given.to_string()),
+            _ => out.push_str(args.get(n - 1).map_or("", |a| a.as_str())),
+        }
+        for _ in 0..len + if braced {2} else {0}{
+            chars.next();
+        }
+    }
+    out
+}
+// words outside quotes replaced by f
+fn replace_words(text: &str, f: impl Fn(&str) -> Option<String>) -> String{
+    let mut out = String::new();
+    let mut quote = None;
+    let mut i = 0;
+    while i < text.len(){
+        let c = text[i..].chars().next().unwrap();
+        if let Some(q) = quote{
+            if c == q{
+                quote = None;
+            }
+        }else if c == '\'' || c == '"' || c == '`'{
+            quote = Some(c);
+        }else if is_word_char(c){
+            // figures like 1fh are one word
+            let len = text[i..].find(|c: char| !is_word_char(c)).unwrap_or(text.len() - i);
+            let word = &text[i..i + len];
+            match f(word).filter(|_| !c.is_ascii_digit()){
+                Some(body) => out.push_str(&body),
+                None => out.push_str(word),
+            }
+            i += len;
+            continue;
+        }
+        out.push(c);
+        i += c.len_utf8();
+    }
+    out
+}
+#[cfg(test)]
+mod tests{
+    use super::*;
+    fn text(source: &str) -> String{
+        preprocess("t.asm", source, &[]).text
+    }
+    #[test]
+    fn define_is_text(){
+        assert_eq!(text("%define SZ qword\nmov SZ [rax], 1"), "mov qword [rax], 1\n");
+        assert_eq!(text("%define ARG [rbp+16]\nmov rax, ARG ; ARG"), "mov rax, [rbp+16] ; [rbp+16]\n");
+        assert_eq!(text("%define N 1+1\n%define M N*2\ndd M, 'M'"), "dd 1+1*2, 'M'\n");
+        assert_eq!(text("%define a a+1\na:"), "a+1:\n");
+        assert_eq!(text("%define X 1\n%undef X\ndb X"), "db X\n");
+    }
+    #[test]
+    fn assign_is_evaluated_once(){
+        assert_eq!(text("%assign i 1\n%assign i i*2+1\n%define j i\n%assign i 7\ndb i, j"), "db 7, 7\n");
+        assert!(!preprocess("t.asm", "%assign i label", &[]).diagnostics.is_empty());
+    }
+}